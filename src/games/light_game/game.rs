@@ -4,180 +4,92 @@ use ggez::event::{KeyCode, KeyMods};
 use ggez::graphics;
 use ggez::{Context, GameResult};
 
-use super::super::super::neural_network::environment;
-
-pub struct Board {
-    pub board: Vec<Vec<u8>>,
-    pub last_chosen: (usize, usize),
-}
-
-impl Board {
-    pub fn new(size: usize) -> Self {
-        let mut board = vec![];
-        let sqrt_size = f32::sqrt(size as f32).floor();
-
-        let mut tmp = vec![];
-        let mut count = 1;
-        for _ in 0..size as u32 {
-            tmp.push(0);
-            if count as f32 % sqrt_size == 0.0 {
-                board.push(tmp);
-                tmp = vec![];
-            }
-            count += 1;
-        }
-        Board {
-            board: board,
-            last_chosen: (0, 0),
-        }
-    }
-
-    pub fn check_finish(&mut self) -> (bool, u32) {
-        let mut total = 0;
-        let mut res = true;
-        self.board.iter().for_each(|row| {
-            row.iter().for_each(|col| {
-                if col == &0_u8 {
-                    res = false
-                } else {
-                    total += 1;
-                };
-            })
-        });
-        (res, total)
-    }
-
-    pub fn update_tile(&mut self, index: usize) -> bool {
-        let len = self.board.len();
-        let row = index / len;
-        let col = index % len;
-        match self.board[row][col] {
-            0 => self.board[row][col] = 1,
-            1 => self.board[row][col] = 0,
-            _ => (),
-        }
-        match self.board[row][col] {
-            1 => true,
-            0 => false,
-            _ => false,
-        }
-    }
-}
+use super::super::super::neural_network::environment::Environment;
+use super::super::super::neural_network::nn::NN;
+use super::super::super::neural_network::task::Episode;
+use super::task::{save_champion, LightGameEpisode, LightGameTask, CHECKPOINT};
 
 struct MainState {
-    env: environment::Environment,
-    boards: Vec<Board>,
-    round: u32,
-    input_size: usize,
+    env: Environment,
+    task: LightGameTask,
+    replays: Vec<(NN, LightGameEpisode)>,
     draw_no: u32,
     best: u32,
     all_time_best: u32,
-    last_best: u32,
 }
 
-// env is the environment
-// board is a list of boards, the index is associated with the species at same index in env
-// round tells how many round or moves that has been produced in this generation
+// The viewer watches a run of the light game. The generations are made with Environment::evolve,
+// the same way as when it is trained headless, and replays is a copy of the first draw_no
+// networks of the generation playing a board one move every frame, it does not change the fitness
 
 impl MainState {
-    pub fn new(
-        ctx: &mut Context,
-        env: environment::Environment,
-        input_size: usize,
-        draw_no: u32,
-    ) -> Self {
+    pub fn new(ctx: &mut Context, env: Environment, task: LightGameTask, draw_no: u32) -> Self {
         graphics::set_window_title(ctx, "LIGHT GAME");
 
-        let mut boards = vec![];
-
-        for _ in 0..env.species.len() {
-            boards.push(Board::new(input_size))
-        }
-
         let mut draw_no = draw_no;
         if draw_no > env.species.len() as u32 {
             draw_no = env.species.len() as u32
         }
 
-        MainState {
-            env: env,
-            boards: boards,
-            input_size: input_size,
-            round: 0,
-            draw_no: draw_no,
+        let mut state = MainState {
+            env,
+            task,
+            replays: vec![],
+            draw_no,
             best: 0,
             all_time_best: 0,
-            last_best: 0,
-        }
+        };
+        state.start_replays();
+        state
+    }
+
+    fn start_replays(&mut self) {
+        // every network plays the same board, it is picked by the generation so the
+        // random numbers of the run are not used
+        let seed = self.env.generation as u64;
+        self.replays = self.env.species[..self.draw_no as usize]
+            .iter()
+            .map(|nn| {
+                let mut nn = NN::clone(nn);
+                nn.reset_state();
+                (nn, self.task.episode(seed))
+            })
+            .collect();
+        self.best = 0;
     }
 }
 
 impl event::EventHandler for MainState {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
-        if self.round as f32
-            > self.boards[0].board[0].len() as f32 * self.boards[0].board.len() as f32 * 1.5
-        {
-            let mut new_boards = vec![];
-            for _ in 0..self.env.species.len() {
-                new_boards.push(Board::new(self.input_size));
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        if self.replays.iter().all(|(_, episode)| episode.done()) {
+            // scores the whole generation with the task and makes the next one
+            if let Err(e) = self.env.evolve(&self.task, 1) {
+                println!("Can not train the light game: {}", e);
+                event::quit(ctx);
+                return Ok(());
             }
-            self.boards = new_boards;
-
-            if self.env.generation - self.last_best > 1000 {
-                self.env.next_generation(true);
-                self.last_best = self.env.generation;
-                self.best = 0;
-            } else {
-                self.env.next_generation(false);
+            self.start_replays();
+        }
+        for (nn, episode) in self.replays.iter_mut() {
+            if episode.done() {
+                continue;
             }
-            self.round = 0;
+            let outputs = nn.activate(&episode.observation());
+            episode.step(&outputs);
+            self.best = self.best.max(episode.tiles_on());
         }
         if self.best > self.all_time_best {
             self.all_time_best = self.best;
         }
-        for i in 0..self.env.species.len() {
-            let (res, total) = self.boards[i].check_finish();
-            if total > self.best {
-                self.best = total;
-                self.last_best = self.env.generation;
-            }
-            if res {
-                // std::thread::sleep(std::time::Duration::from_millis(100));
-                continue;
-            }
-            if self.boards[i].last_chosen.1 > 4 {
-                self.env.species[i].reward(-1.0);
-                continue;
-            }
-
-            let mut input_values: Vec<f32> = vec![];
-            self.boards[i]
-                .board
-                .iter()
-                .for_each(|row| row.iter().for_each(|col| input_values.push(*col as f32)));
-
-            // Penalty or reward
-
-            let index_out = self.env.species[i].update(input_values);
-            if self.boards[i].update_tile(index_out) {
-                // if it turns on a tile, give reward
-                self.env.species[i].reward(1.0);
-            } else {
-                self.env.species[i].reward(-1.0);
-            }
-            // gives them a reward of -1.0 for every round
-            // self.env.species[i].reward(-1.0);
-
-            // if the same square is chosen, give them a penalty
-            if index_out == self.boards[i].last_chosen.0 {
-                self.boards[i].last_chosen.1 += 1;
-                // self.env.species[i].reward(-1.0);
-            }
-            self.boards[i].last_chosen.1 = index_out;
-        }
-        self.round += 1;
         Ok(())
     }
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        // the generation is scored, so the champion is the best of it
+        self.env.evaluate(&self.task);
+        save_champion(&mut self.env);
+        false
+    }
+
     fn key_down_event(
         &mut self,
         _ctx: &mut Context,
@@ -185,37 +97,34 @@ impl event::EventHandler for MainState {
         _keymods: KeyMods,
         _repeat: bool,
     ) {
-        match keycode {
-            KeyCode::Up => {
-                self.boards[0].update_tile(0);
-            }
-            KeyCode::Down => {
-                self.boards[1].update_tile(0);
-            }
-            KeyCode::Right => {
-                self.boards[2].update_tile(0);
-            }
-            KeyCode::Left => {
-                self.boards[3].update_tile(0);
-            }
-            _ => {}
+        let board = match keycode {
+            KeyCode::Up => 0,
+            KeyCode::Down => 1,
+            KeyCode::Right => 2,
+            KeyCode::Left => 3,
+            _ => return,
+        };
+        // only the boards that are shown can be changed
+        if let Some((_, episode)) = self.replays.get_mut(board) {
+            episode.board.update_tile(0);
         }
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let (screen_w, screen_h) = graphics::drawable_size(ctx);
-        let canvas_size = f32::sqrt(self.boards[0..self.draw_no as usize].len() as f32);
+        let canvas_size = f32::sqrt(self.replays.len() as f32);
         let canvas_w = screen_w / canvas_size;
         let canvas_h = screen_h / canvas_size;
-        let square_w = canvas_w / self.boards[0].board[0].len() as f32;
-        let square_h = canvas_h / self.boards[0].board.len() as f32;
+        let board = &self.replays[0].1.board.board;
+        let square_w = canvas_w / board[0].len() as f32;
+        let square_h = canvas_h / board.len() as f32;
         graphics::clear(ctx, graphics::WHITE);
 
         let mut count = 0;
 
         let mut canvas_x = 0;
         let mut canvas_y = 0;
-        for canvas in &self.boards[..self.draw_no as usize] {
+        for (_, episode) in &self.replays {
             let new_canvas = graphics::Rect::new(
                 canvas_x as f32 * canvas_w,
                 canvas_y as f32 * canvas_h,
@@ -233,10 +142,8 @@ impl event::EventHandler for MainState {
 
             graphics::draw(ctx, &canvas_boarder_mesh, draw_param_canvas)?;
 
-            let mut board_x = 0;
-            let mut board_y = 0;
-            for row in &canvas.board {
-                for col in row {
+            for (board_y, row) in episode.board.board.iter().enumerate() {
+                for (board_x, col) in row.iter().enumerate() {
                     let mut color = graphics::WHITE;
                     if col == &1 {
                         color = graphics::BLACK;
@@ -265,10 +172,7 @@ impl event::EventHandler for MainState {
 
                     graphics::draw(ctx, &tile_boarder_mesh, draw_param_tile)?;
                     graphics::draw(ctx, &tile_mesh, draw_param_tile)?;
-                    board_x += 1;
                 }
-                board_x = 0;
-                board_y += 1;
             }
             canvas_x += 1;
             count += 1;
@@ -310,13 +214,27 @@ impl event::EventHandler for MainState {
     }
 }
 
-pub fn run(num_species: u32, num_input: u32, num_output: u32, draw_no: u32) -> GameResult {
-    let env = environment::Environment::new(num_species, num_input, num_output);
+pub fn run(
+    num_species: u32,
+    num_input: u32,
+    num_output: u32,
+    draw_no: u32,
+    threads: usize,
+) -> GameResult {
+    let mut env = match LightGameTask::resume_or_new(num_species, num_input, num_output) {
+        Ok(env) => env,
+        Err(e) => {
+            println!("Could not resume from {}: {}", CHECKPOINT, e);
+            return Ok(());
+        }
+    };
+    env.set_threads(threads);
+    let task = LightGameTask::new(num_output as usize);
 
     let game = ggez::ContextBuilder::new("light game", "light game");
     let (ctx, event_loop) = &mut game.build()?;
 
-    let mut state = MainState::new(ctx, env, num_output as usize, draw_no);
+    let mut state = MainState::new(ctx, env, task, draw_no);
     event::run(ctx, event_loop, &mut state)?;
     Ok(())
 }
@@ -1 +1,3 @@
-pub mod game;
\ No newline at end of file
+#[cfg(feature = "viewer")]
+pub mod game;
+pub mod task;
\ No newline at end of file
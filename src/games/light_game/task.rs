@@ -0,0 +1,358 @@
+use super::super::super::neural_network::environment::Environment;
+use super::super::super::neural_network::nn::NN;
+use super::super::super::neural_network::persistence::PersistenceError;
+use super::super::super::neural_network::task::{run_episode, Episode, Task};
+use rand::seq::index;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub struct Board {
+    pub board: Vec<Vec<u8>>,
+    // the last chosen tile and how many times in a row it was chosen again
+    pub last_chosen: (usize, usize),
+}
+
+impl Board {
+    pub fn new(size: usize) -> Self {
+        let mut board = vec![];
+        let sqrt_size = f32::sqrt(size as f32).floor();
+
+        let mut tmp = vec![];
+        for count in 1..=size {
+            tmp.push(0);
+            if count as f32 % sqrt_size == 0.0 {
+                board.push(tmp);
+                tmp = vec![];
+            }
+        }
+        Board {
+            board,
+            last_chosen: (0, 0),
+        }
+    }
+
+    pub fn check_finish(&self) -> (bool, u32) {
+        let mut total = 0;
+        let mut res = true;
+        self.board.iter().for_each(|row| {
+            row.iter().for_each(|col| {
+                if col == &0_u8 {
+                    res = false
+                } else {
+                    total += 1;
+                };
+            })
+        });
+        (res, total)
+    }
+
+    pub fn update_tile(&mut self, index: usize) -> bool {
+        let len = self.board.len();
+        let row = index / len;
+        let col = index % len;
+        match self.board[row][col] {
+            0 => self.board[row][col] = 1,
+            1 => self.board[row][col] = 0,
+            _ => (),
+        }
+        match self.board[row][col] {
+            1 => true,
+            0 => false,
+            _ => false,
+        }
+    }
+}
+
+// the run is saved here every 10 generations
+pub const CHECKPOINT: &str = "light_game.checkpoint";
+// the best network is saved here when the game stops
+pub const CHAMPION: &str = "champion.json";
+
+// The light game as a task: every network gets its own board and turns tiles on and off.
+// It has no graphics, so it can be trained as fast as the cpu allows, the viewer in
+// game.rs plays the episodes one move every frame
+pub struct LightGameTask {
+    // the number of tiles, which is the number of inputs and outputs
+    pub size: usize,
+    // the number of tiles that are on when the game starts, the seed of the episode
+    // decides which, with 0 every episode starts from the same empty board
+    pub lit_tiles: usize,
+}
+
+impl LightGameTask {
+    pub fn new(size: usize) -> Self {
+        LightGameTask { size, lit_tiles: 0 }
+    }
+
+    pub fn episode(&self, seed: u64) -> LightGameEpisode {
+        let mut board = Board::new(self.size);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        for tile in index::sample(&mut rng, self.size, self.lit_tiles.min(self.size)) {
+            board.update_tile(tile);
+        }
+        LightGameEpisode { board, round: 0 }
+    }
+
+    pub fn resume_or_new(
+        num_species: u32,
+        num_input: u32,
+        num_output: u32,
+    ) -> Result<Environment, PersistenceError> {
+        LightGameTask::resume_or_new_from(CHECKPOINT, num_species, num_input, num_output)
+    }
+
+    fn resume_or_new_from<P: AsRef<Path>>(
+        path: P,
+        num_species: u32,
+        num_input: u32,
+        num_output: u32,
+    ) -> Result<Environment, PersistenceError> {
+        // continue the last run if it was stopped, a new run is only started when there is
+        // no checkpoint, one that can not be read would be overwritten by the new run
+        let path = path.as_ref();
+        let mut env = match Environment::resume(path) {
+            Ok(env) => {
+                println!(
+                    "Resuming from {} at generation {}",
+                    path.display(),
+                    env.generation
+                );
+                env
+            }
+            Err(PersistenceError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                let mut env = Environment::new(num_species, num_input, num_output);
+                // the agent has to remember which tiles it has toggled
+                env.set_recurrent(true);
+                env
+            }
+            Err(e) => return Err(e),
+        };
+        env.set_auto_checkpoint(10, path);
+        Ok(env)
+    }
+}
+
+impl Task for LightGameTask {
+    fn input_size(&self) -> usize {
+        self.size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn evaluate(&self, nn: &mut NN, seed: u64) -> f32 {
+        run_episode(nn, &mut self.episode(seed))
+    }
+}
+
+// round tells how many moves that has been made on the board
+pub struct LightGameEpisode {
+    pub board: Board,
+    pub round: u32,
+}
+
+impl LightGameEpisode {
+    pub fn tiles_on(&self) -> u32 {
+        self.board.check_finish().1
+    }
+}
+
+impl Episode for LightGameEpisode {
+    fn observation(&self) -> Vec<f32> {
+        let mut input_values: Vec<f32> = vec![];
+        self.board
+            .board
+            .iter()
+            .for_each(|row| row.iter().for_each(|col| input_values.push(*col as f32)));
+        input_values
+    }
+
+    fn step(&mut self, outputs: &[f32]) -> f32 {
+        self.round += 1;
+        // a network that keeps choosing the same tile is stuck for the rest of the episode
+        if self.board.last_chosen.1 > 4 {
+            return -1.0;
+        }
+
+        // Penalty or reward
+        let index_out = NN::argmax(outputs);
+        let reward = if self.board.update_tile(index_out) {
+            // if it turns on a tile, give reward
+            1.0
+        } else {
+            -1.0
+        };
+
+        // counts how many times in a row the same square is chosen
+        if index_out == self.board.last_chosen.0 {
+            self.board.last_chosen.1 += 1;
+        } else {
+            self.board.last_chosen = (index_out, 0);
+        }
+        reward
+    }
+
+    fn done(&self) -> bool {
+        // every board gets one and a half moves per tile, or until every tile is on
+        let tiles = self.board.board[0].len() * self.board.board.len();
+        self.round as f32 > tiles as f32 * 1.5 || self.tiles_on() as usize == tiles
+    }
+}
+
+pub fn save_champion(env: &mut Environment) {
+    // keep the best network, so it is not lost when the game stops
+    if !env.species.is_empty() {
+        match env.get_best_specie().save(CHAMPION) {
+            Ok(_) => println!("Saved the best network to {}", CHAMPION),
+            Err(e) => println!("Could not save the best network: {}", e),
+        }
+    }
+}
+
+// Trains without a window, for machines without a display.
+// threads is the number of threads the networks are evaluated on, 0 uses every core
+pub fn run_headless(
+    num_species: u32,
+    num_input: u32,
+    num_output: u32,
+    generations: u32,
+    threads: usize,
+) {
+    let mut env = match LightGameTask::resume_or_new(num_species, num_input, num_output) {
+        Ok(env) => env,
+        Err(e) => {
+            println!("Could not resume from {}: {}", CHECKPOINT, e);
+            return;
+        }
+    };
+    env.set_threads(threads);
+    let task = LightGameTask::new(num_output as usize);
+    for _ in 0..generations {
+        if let Err(e) = env.evolve(&task, 1) {
+            println!("Can not train the light game: {}", e);
+            return;
+        }
+        println!(
+            "Generation: {}, best fitness: {}",
+            env.generation, env.best_fitness
+        );
+    }
+    // the last generation is scored, so the champion is the best of it
+    env.evaluate(&task);
+    save_champion(&mut env);
+}
+
+#[cfg(test)]
+mod test_task {
+    use super::*;
+
+    #[test]
+    fn test_episode() {
+        let task = LightGameTask::new(9);
+        let mut episode = task.episode(0);
+        assert_eq!(episode.observation(), vec![0.0; 9]);
+
+        let mut outputs = vec![0.0; 9];
+        outputs[2] = 1.0;
+        assert_eq!(episode.step(&outputs), 1.0);
+        assert_eq!(episode.tiles_on(), 1);
+        assert_eq!(episode.observation()[2], 1.0);
+        // choosing it again turns it off
+        assert_eq!(episode.step(&outputs), -1.0);
+        assert_eq!(episode.tiles_on(), 0);
+
+        // one and a half moves per tile
+        let mut moves = 2;
+        while !episode.done() {
+            episode.step(&outputs);
+            moves += 1;
+        }
+        assert_eq!(moves, 14);
+    }
+
+    #[test]
+    fn test_stuck() {
+        let task = LightGameTask::new(9);
+        let mut episode = task.episode(0);
+        let mut outputs = vec![0.0; 9];
+        outputs[4] = 1.0;
+
+        // the first choice and five repeats are played
+        for _ in 0..6 {
+            episode.step(&outputs);
+        }
+        assert_eq!(episode.board.last_chosen, (4, 5));
+        assert_eq!(episode.tiles_on(), 0);
+
+        // after that the board does not change, even for another tile
+        outputs[4] = 0.0;
+        outputs[1] = 1.0;
+        assert_eq!(episode.step(&outputs), -1.0);
+        assert_eq!(episode.tiles_on(), 0);
+
+        // choosing another tile in between starts the count again
+        let mut episode = task.episode(0);
+        for i in 0..10 {
+            let mut outputs = vec![0.0; 9];
+            outputs[i % 2] = 1.0;
+            episode.step(&outputs);
+        }
+        assert_eq!(episode.board.last_chosen, (1, 0));
+        let mut outputs = vec![0.0; 9];
+        outputs[4] = 1.0;
+        assert_eq!(episode.step(&outputs), 1.0);
+    }
+
+    #[test]
+    fn test_lit_tiles() {
+        let mut task = LightGameTask::new(9);
+        task.lit_tiles = 3;
+        let episode = task.episode(5);
+        assert_eq!(episode.tiles_on(), 3);
+        assert_eq!(episode.observation(), task.episode(5).observation());
+        // another seed is another board
+        assert!((0..10).any(|seed| task.episode(seed).observation() != episode.observation()));
+
+        task.lit_tiles = 9;
+        assert!(task.episode(1).done());
+    }
+
+    #[test]
+    fn test_evolve() {
+        let task = LightGameTask::new(9);
+        let mut env1 = Environment::with_seed(20, 9, 9, 2);
+        let mut env2 = Environment::with_seed(20, 9, 9, 2);
+        env1.set_recurrent(true);
+        env2.set_recurrent(true);
+        for _ in 0..3 {
+            env1.evolve(&task, 1).unwrap();
+            env2.evolve(&task, 1).unwrap();
+            assert_eq!(env1.species, env2.species);
+            assert_eq!(env1.best_fitness, env2.best_fitness);
+        }
+        assert_eq!(env1.generation, 4);
+    }
+
+    #[test]
+    fn test_resume_or_new() {
+        let path =
+            std::env::temp_dir().join(format!("light_game_{}.checkpoint", std::process::id()));
+
+        // without a checkpoint a new run is started
+        let env = LightGameTask::resume_or_new_from(&path, 10, 9, 9).unwrap();
+        assert_eq!(env.generation, 1);
+        assert_eq!(env.auto_checkpoint.unwrap().path, path);
+
+        // a checkpoint that can not be read is not replaced
+        std::fs::write(&path, b"not a checkpoint").unwrap();
+        assert!(matches!(
+            LightGameTask::resume_or_new_from(&path, 10, 9, 9),
+            Err(PersistenceError::BadMagic)
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a checkpoint");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// Random numbers with other distributions than uniform, used for the weights and biases
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Distribution {
+    Normal {
+        mean: f32,
+        std_dev: f32,
+    },
+    // like normal, but with heavy tails, so a big jump happens once in a while
+    Cauchy {
+        location: f32,
+        scale: f32,
+    },
+    Uniform {
+        low: f32,
+        high: f32,
+    },
+    // normal, but never outside [low, high]
+    TruncatedNormal {
+        mean: f32,
+        std_dev: f32,
+        low: f32,
+        high: f32,
+    },
+}
+
+impl Distribution {
+    pub fn sample(&self, rng: &mut dyn RngCore) -> f32 {
+        match *self {
+            Distribution::Normal { mean, std_dev } => normal(rng, mean, std_dev),
+            Distribution::Cauchy { location, scale } => cauchy(rng, location, scale),
+            Distribution::Uniform { low, high } => uniform(rng, low, high),
+            Distribution::TruncatedNormal {
+                mean,
+                std_dev,
+                low,
+                high,
+            } => truncated_normal(rng, mean, std_dev, low, high),
+        }
+    }
+}
+
+// Box–Muller transform
+pub fn normal(rng: &mut dyn RngCore, mean: f32, std_dev: f32) -> f32 {
+    // u1 is in (0, 1] so the logarithm is finite
+    let u1 = 1.0 - rng.gen::<f32>();
+    let u2 = rng.gen::<f32>();
+    let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+    mean + std_dev * z
+}
+
+pub fn cauchy(rng: &mut dyn RngCore, location: f32, scale: f32) -> f32 {
+    // the inverse of the cumulative distribution function with u in (0, 1), the tails are
+    // kept whole, so a sample can be very big and the weight and bias ranges clamp it
+    let mut u = rng.gen::<f32>();
+    while u == 0.0 {
+        u = rng.gen::<f32>();
+    }
+    location + scale * (PI * (u - 0.5)).tan()
+}
+
+pub fn uniform(rng: &mut dyn RngCore, low: f32, high: f32) -> f32 {
+    low + (high - low) * rng.gen::<f32>()
+}
+
+pub fn truncated_normal(
+    rng: &mut dyn RngCore,
+    mean: f32,
+    std_dev: f32,
+    low: f32,
+    high: f32,
+) -> f32 {
+    // samples until one is inside, if the range is far out in the tail it gives up
+    for _ in 0..100 {
+        let value = normal(rng, mean, std_dev);
+        if value >= low && value <= high {
+            return value;
+        }
+    }
+    uniform(rng, low, high)
+}
+
+#[cfg(test)]
+mod test_distributions {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn samples(distribution: Distribution) -> Vec<f32> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        (0..20000).map(|_| distribution.sample(&mut rng)).collect()
+    }
+
+    fn mean(values: &[f32]) -> f32 {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn test_normal() {
+        let values = samples(Distribution::Normal {
+            mean: 2.0,
+            std_dev: 3.0,
+        });
+        let m = mean(&values);
+        let variance = values.iter().map(|v| (v - m).powi(2)).sum::<f32>() / values.len() as f32;
+
+        assert!((m - 2.0).abs() < 0.1);
+        assert!((variance.sqrt() - 3.0).abs() < 0.1);
+        // unlike the sum of uniforms, a normal distribution goes past 3 standard deviations
+        assert!(values.iter().any(|v| (v - 2.0).abs() > 9.0));
+    }
+
+    #[test]
+    fn test_cauchy() {
+        let mut values = samples(Distribution::Cauchy {
+            location: 1.0,
+            scale: 0.5,
+        });
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // the median is the location and the quartiles are one scale away
+        assert!((values[10000] - 1.0).abs() < 0.05);
+        assert!((values[15000] - values[5000] - 1.0).abs() < 0.1);
+        assert!(values.iter().all(|v| v.is_finite()));
+        // the tails are not cut off, u = 0.0001 would stop at about 3183 scales away
+        assert!(values.iter().any(|v| (v - 1.0).abs() > 0.5 * 3200.0));
+    }
+
+    #[test]
+    // a number between 0 and 1 centered on 0.5
+    fn test_truncated_normal_unit() {
+        let num = truncated_normal(&mut rand::thread_rng(), 0.5, 0.2, 0.0, 1.0);
+        assert!((0.0..=1.0).contains(&num));
+    }
+
+    #[test]
+    // a number between -1 and 1 centered on 0
+    fn test_uniform_unit() {
+        let num = uniform(&mut rand::thread_rng(), -1.0, 1.0);
+        assert!((-1.0..=1.0).contains(&num));
+    }
+
+    #[test]
+    fn test_uniform() {
+        let values = samples(Distribution::Uniform {
+            low: -2.0,
+            high: 4.0,
+        });
+        assert!(values.iter().all(|v| *v >= -2.0 && *v < 4.0));
+        assert!((mean(&values) - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_truncated_normal() {
+        let values = samples(Distribution::TruncatedNormal {
+            mean: 0.0,
+            std_dev: 1.0,
+            low: -0.5,
+            high: 2.0,
+        });
+        assert!(values.iter().all(|v| *v >= -0.5 && *v <= 2.0));
+
+        // far out in the tail
+        let values = samples(Distribution::TruncatedNormal {
+            mean: 0.0,
+            std_dev: 1.0,
+            low: 100.0,
+            high: 101.0,
+        });
+        assert!(values.iter().all(|v| *v >= 100.0 && *v <= 101.0));
+    }
+}
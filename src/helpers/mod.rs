@@ -1,4 +1 @@
-pub mod lib;
-
-pub use lib::gaussian_rand;
-pub use lib::std0;
\ No newline at end of file
+pub mod distributions;
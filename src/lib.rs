@@ -0,0 +1,3 @@
+pub mod games;
+pub mod helpers;
+pub mod neural_network;
@@ -1,10 +1,66 @@
-mod games;
-mod helpers;
-mod neural_network;
+use evolutionary_nn::games::light_game::task;
+use evolutionary_nn::neural_network::benchmark::{run_benchmark, ParityTask};
+use evolutionary_nn::neural_network::environment::Environment;
+
+// the number of generations --headless trains for when it is not given
+const HEADLESS_GENERATIONS: u32 = 100;
+// the number of generations --benchmark tries before it gives up
+const BENCHMARK_GENERATIONS: u32 = 500;
 
 fn main() {
-    match games::light_game::game::run(200, 9, 9, 1) {
+    // --headless [generations] trains the light game without a window,
+    // --benchmark [bits] evolves networks for the parity of the bits, xor when it is not given,
+    // --threads n evaluates the networks on n threads instead of every core
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let threads = match args.iter().position(|arg| arg == "--threads") {
+        Some(i) => match args.get(i + 1).map(|arg| arg.parse()) {
+            Some(Ok(threads)) => threads,
+            Some(Err(e)) => panic!("invalid number of threads: {:?}", e),
+            None => panic!("--threads needs a number"),
+        },
+        None => 0,
+    };
+    if let Some(i) = args.iter().position(|arg| arg == "--benchmark") {
+        let task = match args.get(i + 1).map(|arg| arg.parse()) {
+            Some(Ok(bits)) => ParityTask::new(bits),
+            Some(Err(e)) => panic!("invalid number of bits: {:?}", e),
+            None => ParityTask::xor(),
+        };
+        benchmark(task, threads);
+        return;
+    }
+    match args.iter().position(|arg| arg == "--headless") {
+        Some(i) => {
+            let generations = match args.get(i + 1).map(|arg| arg.parse()) {
+                Some(Ok(generations)) => generations,
+                Some(Err(e)) => panic!("invalid number of generations: {:?}", e),
+                None => HEADLESS_GENERATIONS,
+            };
+            task::run_headless(200, 9, 9, generations, threads);
+        }
+        None => run_viewer(threads),
+    }
+}
+
+fn benchmark(task: ParityTask, threads: usize) {
+    let mut env = Environment::new(150, task.bits as u32, 1);
+    env.set_threads(threads);
+    match run_benchmark(&mut env, &task, BENCHMARK_GENERATIONS) {
+        Ok(result) => println!("Parity of {} bits: {}", task.bits, result),
+        Err(e) => println!("Can not run the benchmark: {}", e),
+    }
+}
+
+#[cfg(feature = "viewer")]
+fn run_viewer(threads: usize) {
+    match evolutionary_nn::games::light_game::game::run(200, 9, 9, 1, threads) {
         Ok(_) => (),
         Err(e) => panic!("{:?}", e),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(not(feature = "viewer"))]
+fn run_viewer(threads: usize) {
+    println!("Built without the viewer feature, training without a window");
+    task::run_headless(200, 9, 9, HEADLESS_GENERATIONS, threads);
+}
@@ -1,8 +1,10 @@
 use libm;
 use rand::seq::SliceRandom;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
 #[allow(dead_code)]
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum ActivationFunctions {
     None,
     Logistics,
@@ -18,8 +20,7 @@ pub enum ActivationFunctions {
 }
 
 impl ActivationFunctions {
-    pub fn get_random() -> ActivationFunctions {
-        let mut rng = rand::thread_rng();
+    pub fn get_random(rng: &mut dyn RngCore) -> ActivationFunctions {
         let choices = [
             ActivationFunctions::Logistics,
             ActivationFunctions::HyperbolicTangent,
@@ -31,7 +32,7 @@ impl ActivationFunctions {
             ActivationFunctions::Sinusiodial,
             ActivationFunctions::BentIdentity,
         ];
-        *choices.choose(&mut rng).unwrap()
+        *choices.choose(rng).unwrap()
     }
 
     fn logistic_function(value: f32) -> f32 {
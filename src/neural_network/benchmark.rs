@@ -0,0 +1,153 @@
+use super::environment::Environment;
+use super::error::NnError;
+use super::nn::NN;
+use super::task::Task;
+use std::fmt;
+
+// the most fitness a network gets for one case, it has to be worth more than the
+// nodes and connections it takes to solve it, see NN::calculate_fitness
+const SCORE_PER_CASE: f32 = 10.0;
+
+// The parity of some bits: the output should be 1 when an odd number of the inputs are 1,
+// and 0 otherwise. XOR is the parity of 2 bits.
+// They are small problems that can not be solved without a hidden node, so they show if
+// the evolution works at all
+pub struct ParityTask {
+    pub bits: usize,
+}
+
+impl ParityTask {
+    pub fn new(bits: usize) -> Self {
+        ParityTask { bits }
+    }
+
+    pub fn xor() -> Self {
+        ParityTask::new(2)
+    }
+
+    // every combination of the bits, with the output it should give
+    pub fn cases(&self) -> Vec<(Vec<f32>, f32)> {
+        (0..1_u32 << self.bits)
+            .map(|case| {
+                let inputs = (0..self.bits).map(|bit| (case >> bit & 1) as f32).collect();
+                (inputs, (case.count_ones() % 2) as f32)
+            })
+            .collect()
+    }
+
+    // the task is solved when every output is on the right side of 0.5
+    pub fn solves(&self, nn: &mut NN) -> bool {
+        self.cases().iter().all(|(inputs, expected)| {
+            nn.reset_state();
+            (nn.activate(inputs)[0] > 0.5) == (*expected > 0.5)
+        })
+    }
+}
+
+impl Task for ParityTask {
+    fn input_size(&self) -> usize {
+        self.bits
+    }
+
+    fn output_size(&self) -> usize {
+        1
+    }
+
+    fn evaluate(&self, nn: &mut NN, _seed: u64) -> f32 {
+        // every case is scored by how close the output is, an output that is off by
+        // 1 or more scores nothing
+        self.cases()
+            .iter()
+            .map(|(inputs, expected)| {
+                nn.reset_state();
+                let error = nn.activate(inputs)[0] - expected;
+                SCORE_PER_CASE * (1.0 - (error * error).min(1.0))
+            })
+            .sum()
+    }
+}
+
+// How a benchmark went, generations is None when it was not solved
+#[derive(Debug, PartialEq)]
+pub struct BenchmarkResult {
+    pub generations: Option<u32>,
+    // the size of the network that solved it, or the best network when it was not solved
+    pub nodes: usize,
+    pub connections: usize,
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.generations {
+            Some(generations) => write!(f, "solved in {} generations", generations)?,
+            None => write!(f, "not solved")?,
+        }
+        write!(
+            f,
+            ", the network has {} nodes and {} connections",
+            self.nodes, self.connections
+        )
+    }
+}
+
+// Evolves the population until a network solves the task, or max_generations have been tried
+pub fn run_benchmark(
+    env: &mut Environment,
+    task: &ParityTask,
+    max_generations: u32,
+) -> Result<BenchmarkResult, NnError> {
+    env.check_task(task)?;
+    for generation in 1..=max_generations {
+        env.evaluate(task);
+        if let Some(i) = env.species.iter_mut().position(|nn| task.solves(nn)) {
+            let nn = &env.species[i];
+            return Ok(BenchmarkResult {
+                generations: Some(generation),
+                nodes: nn.node_genes.len(),
+                connections: nn
+                    .connection_genes
+                    .iter()
+                    .filter(|conn| conn.enabled)
+                    .count(),
+            });
+        }
+        if generation < max_generations {
+            env.next_generation();
+        }
+    }
+
+    let best = env.get_best_specie();
+    Ok(BenchmarkResult {
+        generations: None,
+        nodes: best.node_genes.len(),
+        connections: best
+            .connection_genes
+            .iter()
+            .filter(|conn| conn.enabled)
+            .count(),
+    })
+}
+
+#[cfg(test)]
+mod test_benchmark {
+    use super::*;
+
+    #[test]
+    fn test_cases() {
+        let xor = ParityTask::xor().cases();
+        assert_eq!(
+            xor,
+            vec![
+                (vec![0.0, 0.0], 0.0),
+                (vec![1.0, 0.0], 1.0),
+                (vec![0.0, 1.0], 1.0),
+                (vec![1.0, 1.0], 0.0),
+            ]
+        );
+
+        let parity = ParityTask::new(3).cases();
+        assert_eq!(parity.len(), 8);
+        assert_eq!(parity[7], (vec![1.0, 1.0, 1.0], 1.0));
+        assert_eq!(parity[6], (vec![0.0, 1.0, 1.0], 0.0));
+    }
+}
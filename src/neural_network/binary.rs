@@ -0,0 +1,478 @@
+use super::activation_functions::ActivationFunctions;
+use super::genes::{ConnectionGene, NodeGene, NodeType};
+use super::nn::NN;
+use super::persistence::PersistenceError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// A compact binary format for saving many networks, all numbers are little endian.
+//
+// header:     magic "NNGB", version (u8)
+// population: number of networks (u32), then the networks
+// network:    recurrent (u8), fitness (f32), mutation step (f32, since version 2),
+//             number of layers (u32), every layer is its length (u32) and node ids (u32),
+//             number of nodes (u32), every node is
+//                 id (u32), node type (u8), bias (f32), squash (u8), value (f32), activation (f32)
+//             number of connections (u32), every connection is
+//                 from (u32), to (u32), weight (f32), gater (i32), innovation (u32),
+//                 enabled (u8, since version 3)
+//
+// The nodes are written sorted by id, so the same network always gives the same bytes
+pub const MAGIC: [u8; 4] = *b"NNGB";
+pub const BINARY_VERSION: u8 = 3;
+
+// the byte of a node type or activation function is its index in these lists
+const NODE_TYPES: [NodeType; 3] = [NodeType::Input, NodeType::Hidden, NodeType::Output];
+const SQUASHES: [ActivationFunctions; 11] = [
+    ActivationFunctions::None,
+    ActivationFunctions::Logistics,
+    ActivationFunctions::HyperbolicTangent,
+    ActivationFunctions::Identity,
+    ActivationFunctions::BinaryStep,
+    ActivationFunctions::ReLU,
+    ActivationFunctions::Softsign,
+    ActivationFunctions::Gaussian,
+    ActivationFunctions::Sinusiodial,
+    ActivationFunctions::BentIdentity,
+    ActivationFunctions::SELU,
+];
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> Result<(), PersistenceError> {
+    writer.write_all(&[value])?;
+    Ok(())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), PersistenceError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_i32<W: Write>(writer: &mut W, value: i32) -> Result<(), PersistenceError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> Result<(), PersistenceError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], PersistenceError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, PersistenceError> {
+    Ok(read_bytes::<R, 1>(reader)?[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, PersistenceError> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32, PersistenceError> {
+    Ok(i32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> Result<f32, PersistenceError> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn write_len<W: Write>(writer: &mut W, len: usize) -> Result<(), PersistenceError> {
+    write_u32(writer, len as u32)
+}
+
+fn tag<T: PartialEq>(list: &[T], value: &T) -> u8 {
+    list.iter().position(|item| item == value).unwrap() as u8
+}
+
+fn from_tag<T: Copy>(list: &[T], tag: u8) -> Result<T, PersistenceError> {
+    match list.get(tag as usize) {
+        Some(value) => Ok(*value),
+        None => Err(PersistenceError::InvalidTag(tag)),
+    }
+}
+
+pub fn write_header<W: Write>(writer: &mut W) -> Result<(), PersistenceError> {
+    writer.write_all(&MAGIC)?;
+    write_u8(writer, BINARY_VERSION)
+}
+
+// returns the version, older versions can still be read
+pub fn read_header<R: Read>(reader: &mut R) -> Result<u8, PersistenceError> {
+    if read_bytes::<R, 4>(reader)? != MAGIC {
+        return Err(PersistenceError::BadMagic);
+    }
+    match read_u8(reader)? {
+        version @ 1..=BINARY_VERSION => Ok(version),
+        version => Err(PersistenceError::UnsupportedVersion(Some(version as u64))),
+    }
+}
+
+impl NN {
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), PersistenceError> {
+        // only the network, without the header
+        write_u8(writer, self.recurrent as u8)?;
+        write_f32(writer, self.fitness)?;
+        write_f32(writer, self.mutation_step)?;
+
+        write_len(writer, self.node_genes_network.len())?;
+        for layer in &self.node_genes_network {
+            write_len(writer, layer.len())?;
+            for id in layer {
+                write_u32(writer, *id)?;
+            }
+        }
+
+        let mut nodes: Vec<&NodeGene> = self.node_genes.values().collect();
+        nodes.sort_by_key(|node| node.id);
+        write_len(writer, nodes.len())?;
+        for node in nodes {
+            write_u32(writer, node.id)?;
+            write_u8(writer, tag(&NODE_TYPES, &node.node_type))?;
+            write_f32(writer, node.bias)?;
+            write_u8(writer, tag(&SQUASHES, &node.squash))?;
+            write_f32(writer, node.value)?;
+            write_f32(writer, node.activation)?;
+        }
+
+        write_len(writer, self.connection_genes.len())?;
+        for conn in &self.connection_genes {
+            write_u32(writer, conn.from)?;
+            write_u32(writer, conn.to)?;
+            write_f32(writer, conn.weight)?;
+            write_i32(writer, conn.gater)?;
+            write_u32(writer, conn.innovation)?;
+            write_u8(writer, conn.enabled as u8)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(reader: &mut R, version: u8) -> Result<NN, PersistenceError> {
+        let recurrent = read_u8(reader)? != 0;
+        let fitness = read_f32(reader)?;
+        let mutation_step = if version >= 2 { read_f32(reader)? } else { 1.0 };
+
+        // the lengths are not used to reserve memory, a broken file could claim anything
+        let mut node_genes_network = vec![];
+        for _ in 0..read_u32(reader)? {
+            let mut layer = vec![];
+            for _ in 0..read_u32(reader)? {
+                layer.push(read_u32(reader)?);
+            }
+            node_genes_network.push(layer);
+        }
+
+        let mut node_genes = HashMap::new();
+        for _ in 0..read_u32(reader)? {
+            let id = read_u32(reader)?;
+            let node_type = from_tag(&NODE_TYPES, read_u8(reader)?)?;
+            let bias = read_f32(reader)?;
+            let squash = from_tag(&SQUASHES, read_u8(reader)?)?;
+            let mut node = NodeGene::new(id, node_type, bias, squash, read_f32(reader)?);
+            node.activation = read_f32(reader)?;
+            node_genes.insert(id, node);
+        }
+
+        let mut connection_genes = vec![];
+        for _ in 0..read_u32(reader)? {
+            let mut conn = ConnectionGene::new(
+                read_u32(reader)?,
+                read_u32(reader)?,
+                read_f32(reader)?,
+                read_i32(reader)?,
+                read_u32(reader)?,
+            );
+            if version >= 3 {
+                conn.enabled = read_u8(reader)? != 0;
+            }
+            connection_genes.push(conn);
+        }
+
+        let nn = NN {
+            node_genes_network,
+            node_genes,
+            connection_genes,
+            fitness,
+            evaluation_order: None,
+            recurrent,
+            mutation_step,
+        };
+        nn.validate().map_err(PersistenceError::InvalidNetwork)?;
+        Ok(nn)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PersistenceError> {
+        let mut bytes = vec![];
+        write_header(&mut bytes)?;
+        self.write_binary(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<NN, PersistenceError> {
+        let mut reader = bytes;
+        let version = read_header(&mut reader)?;
+        NN::read_binary(&mut reader, version)
+    }
+}
+
+pub fn write_population<W: Write>(
+    writer: &mut W,
+    population: &[NN],
+) -> Result<(), PersistenceError> {
+    write_header(writer)?;
+    write_len(writer, population.len())?;
+    for nn in population {
+        nn.write_binary(writer)?;
+    }
+    Ok(())
+}
+
+// Reads the networks of a population one at a time, so the whole file is never in memory
+pub struct PopulationReader<R: Read> {
+    reader: R,
+    version: u8,
+    remaining: u32,
+}
+
+impl<R: Read> PopulationReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, PersistenceError> {
+        let version = read_header(&mut reader)?;
+        let remaining = read_u32(&mut reader)?;
+        Ok(PopulationReader {
+            reader,
+            version,
+            remaining,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        // networks that are not read yet
+        self.remaining as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+impl<R: Read> Iterator for PopulationReader<R> {
+    type Item = Result<NN, PersistenceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            return None;
+        }
+        self.remaining -= 1;
+        let nn = NN::read_binary(&mut self.reader, self.version);
+        if nn.is_err() {
+            // the rest of the file can not be found after a broken network
+            self.remaining = 0;
+        }
+        Some(nn)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // it stops early at a broken network
+        (0, Some(self.len()))
+    }
+}
+
+pub fn read_population<R: Read>(reader: R) -> Result<Vec<NN>, PersistenceError> {
+    PopulationReader::new(reader)?.collect()
+}
+
+pub fn save_population<P: AsRef<Path>>(path: P, population: &[NN]) -> Result<(), PersistenceError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_population(&mut writer, population)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_population<P: AsRef<Path>>(path: P) -> Result<Vec<NN>, PersistenceError> {
+    read_population(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod test_binary {
+    use super::super::innovation::InnovationTracker;
+    use super::super::mutation::{MutationConfig, WeightConfig};
+    use super::*;
+
+    fn population() -> Vec<NN> {
+        let mut innovation = InnovationTracker::new(5);
+        let mut population = vec![];
+        for i in 0..10 {
+            let input = vec![
+                NodeGene::new(0, NodeType::Input, 0.1, ActivationFunctions::None, 0.0),
+                NodeGene::new(1, NodeType::Input, -0.7, ActivationFunctions::ReLU, 0.0),
+                NodeGene::new(2, NodeType::Input, 1e-9, ActivationFunctions::SELU, 0.0),
+            ];
+            let output = vec![
+                NodeGene::new(
+                    3,
+                    NodeType::Output,
+                    0.3,
+                    ActivationFunctions::Logistics,
+                    0.0,
+                ),
+                NodeGene::new(
+                    4,
+                    NodeType::Output,
+                    -2.5,
+                    ActivationFunctions::Gaussian,
+                    0.0,
+                ),
+            ];
+            let mut nn = NN::new(
+                input,
+                output,
+                &mut innovation,
+                &WeightConfig::default(),
+                &mut rand::thread_rng(),
+            );
+            nn.recurrent = i % 2 == 0;
+            for _ in 0..i * 5 {
+                nn.mutate(
+                    &mut innovation,
+                    &MutationConfig::default(),
+                    &mut rand::thread_rng(),
+                );
+            }
+            nn.fitness = i as f32 / 3.0;
+            nn.activate(&[0.3, -1.0, 2.0]);
+            population.push(nn);
+        }
+        population
+    }
+
+    fn assert_bit_exact(nn1: &NN, nn2: &NN) {
+        assert_eq!(nn1, nn2);
+        assert_eq!(nn1.fitness.to_bits(), nn2.fitness.to_bits());
+        assert_eq!(nn1.mutation_step.to_bits(), nn2.mutation_step.to_bits());
+        for (conn1, conn2) in nn1.connection_genes.iter().zip(&nn2.connection_genes) {
+            assert_eq!(conn1.weight.to_bits(), conn2.weight.to_bits());
+        }
+        for (id, node) in &nn1.node_genes {
+            assert_eq!(node.bias.to_bits(), nn2.node_genes[id].bias.to_bits());
+            assert_eq!(
+                node.activation.to_bits(),
+                nn2.node_genes[id].activation.to_bits()
+            );
+        }
+    }
+
+    #[test]
+    fn test_nn_round_trip() {
+        for nn in population() {
+            let bytes = nn.to_bytes().unwrap();
+            assert_eq!(&bytes[..5], b"NNGB\x03");
+            assert_bit_exact(&NN::from_bytes(&bytes).unwrap(), &nn);
+
+            // the same network gives the same bytes
+            assert_eq!(NN::clone(&nn).to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_population_round_trip() {
+        let population = population();
+        let mut bytes = vec![];
+        write_population(&mut bytes, &population).unwrap();
+
+        let mut reader = PopulationReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.len(), 10);
+        reader.next().unwrap().unwrap();
+        assert_eq!(reader.len(), 9);
+        assert_eq!(reader.size_hint(), (0, Some(9)));
+        assert_eq!(reader.by_ref().count(), 9);
+        assert!(reader.is_empty());
+
+        let loaded = read_population(&bytes[..]).unwrap();
+        assert_eq!(loaded.len(), population.len());
+        for (nn1, nn2) in loaded.iter().zip(&population) {
+            assert_bit_exact(nn1, nn2);
+        }
+
+        // json is a lot bigger
+        let json: usize = population
+            .iter()
+            .map(|nn| nn.to_json().unwrap().len())
+            .sum();
+        assert!(bytes.len() * 3 < json);
+    }
+
+    #[test]
+    fn test_save_and_load_population() {
+        let path = std::env::temp_dir().join(format!("nn_test_{}.bin", std::process::id()));
+        let population = population();
+
+        save_population(&path, &population).unwrap();
+        let loaded = load_population(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, population);
+    }
+
+    #[test]
+    fn test_read_older_versions() {
+        let mut nn = population().remove(3);
+        nn.mutation_step = 0.25;
+        nn.connection_genes[0].enabled = false;
+        let bytes = nn.to_bytes().unwrap();
+
+        // version 2 had no enabled byte at the end of the connections
+        let start = bytes.len() - nn.connection_genes.len() * 21;
+        let mut version2 = bytes[..start].to_vec();
+        version2[4] = 2;
+        for conn in bytes[start..].chunks(21) {
+            version2.extend_from_slice(&conn[..20]);
+        }
+        // and version 1 had no mutation step after the fitness
+        let mut version1 = version2[..10].to_vec();
+        version1[4] = 1;
+        version1.extend_from_slice(&version2[14..]);
+
+        nn.connection_genes
+            .iter_mut()
+            .for_each(|conn| conn.enabled = true);
+        let loaded = NN::from_bytes(&version2).unwrap();
+        assert_bit_exact(&loaded, &nn);
+
+        nn.mutation_step = 1.0;
+        let loaded = NN::from_bytes(&version1).unwrap();
+        assert_bit_exact(&loaded, &nn);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        // the first network is not mutated, it has two layers
+        let bytes = population()[0].to_bytes().unwrap();
+
+        assert!(matches!(
+            NN::from_bytes(b"JSON{}"),
+            Err(PersistenceError::BadMagic)
+        ));
+        let mut newer = bytes.clone();
+        newer[4] = BINARY_VERSION + 1;
+        assert!(matches!(
+            NN::from_bytes(&newer),
+            Err(PersistenceError::UnsupportedVersion(Some(_)))
+        ));
+        assert!(matches!(
+            NN::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PersistenceError::Io(_))
+        ));
+
+        // the squash of the first node, after the header, the layers and its id, type and bias
+        let mut bad_tag = bytes.clone();
+        bad_tag[5 + 9 + 4 + (4 + 3 * 4) + (4 + 2 * 4) + 4 + 4 + 1 + 4] = 200;
+        assert!(matches!(
+            NN::from_bytes(&bad_tag),
+            Err(PersistenceError::InvalidTag(200))
+        ));
+    }
+}
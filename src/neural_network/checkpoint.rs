@@ -0,0 +1,318 @@
+use super::binary::{read_population, write_population};
+use super::environment::Environment;
+use super::genes::NodeGene;
+use super::innovation::InnovationTracker;
+use super::mutation::MutationConfig;
+use super::persistence::PersistenceError;
+use super::selection::SelectionStrategy;
+use super::species::{SpeciationConfig, Species, StagnationConfig};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// A checkpoint is everything needed to continue a run where it was stopped:
+//
+// magic "NNCP", version (u8)
+// the environment without the population (bincode), with the innovation history, species,
+// stagnation counters, configuration and the state of the random number generator
+// the population, in the binary format from binary.rs
+//
+// version 5 added the evaluation config, version 4 can still be read and gets the default,
+// the older versions saved another mutation config and can not be resumed
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"NNCP";
+pub const CHECKPOINT_VERSION: u8 = 5;
+
+// Saves a checkpoint to path every `every` generations
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AutoCheckpoint {
+    pub every: u32,
+    pub path: PathBuf,
+}
+
+// the selection strategy is saved as its SelectionConfig
+pub mod serde_selection {
+    use super::super::selection::{SelectionConfig, SelectionStrategy, TruncationSelection};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(
+        selection: &Box<dyn SelectionStrategy>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        selection.config().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn SelectionStrategy>, D::Error> {
+        match Option::<SelectionConfig>::deserialize(deserializer)? {
+            Some(config) => Ok(config.strategy()),
+            None => Ok(Box::new(TruncationSelection { fraction: 0.5 })),
+        }
+    }
+}
+
+// the environment as a version 4 checkpoint saved it, before the evaluation config
+#[derive(Deserialize)]
+struct EnvironmentV4 {
+    generation: u32,
+    innovation: InnovationTracker,
+    species_list: Vec<Species>,
+    speciation: SpeciationConfig,
+    next_species_id: u32,
+    #[serde(with = "serde_selection")]
+    selection: Box<dyn SelectionStrategy>,
+    elitism: usize,
+    stagnation: StagnationConfig,
+    best_fitness: f32,
+    last_improved: u32,
+    input_layer: Vec<NodeGene>,
+    output_layer: Vec<NodeGene>,
+    recurrent: bool,
+    mutation: MutationConfig,
+    rng: ChaCha8Rng,
+    auto_checkpoint: Option<AutoCheckpoint>,
+}
+
+impl EnvironmentV4 {
+    fn upgrade(self) -> Environment {
+        let mut env = Environment::with_seed(0, 0, 0, 0);
+        env.generation = self.generation;
+        env.innovation = self.innovation;
+        env.species_list = self.species_list;
+        env.speciation = self.speciation;
+        env.next_species_id = self.next_species_id;
+        env.selection = self.selection;
+        env.elitism = self.elitism;
+        env.stagnation = self.stagnation;
+        env.best_fitness = self.best_fitness;
+        env.last_improved = self.last_improved;
+        env.input_layer = self.input_layer;
+        env.output_layer = self.output_layer;
+        env.recurrent = self.recurrent;
+        env.mutation = self.mutation;
+        env.rng = self.rng;
+        env.auto_checkpoint = self.auto_checkpoint;
+        env
+    }
+}
+
+impl Environment {
+    pub fn write_checkpoint<W: Write>(&self, writer: &mut W) -> Result<(), PersistenceError> {
+        writer.write_all(&CHECKPOINT_MAGIC)?;
+        writer.write_all(&[CHECKPOINT_VERSION])?;
+        bincode::serialize_into(&mut *writer, self)?;
+        write_population(writer, &self.species)
+    }
+
+    pub fn read_checkpoint<R: Read>(reader: &mut R) -> Result<Environment, PersistenceError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if header[..4] != CHECKPOINT_MAGIC {
+            return Err(PersistenceError::BadMagic);
+        }
+        let mut env: Environment = match header[4] {
+            4 => bincode::deserialize_from::<_, EnvironmentV4>(&mut *reader)?.upgrade(),
+            CHECKPOINT_VERSION => bincode::deserialize_from(&mut *reader)?,
+            version => return Err(PersistenceError::UnsupportedVersion(Some(version as u64))),
+        };
+        env.species = read_population(reader)?;
+        Ok(env)
+    }
+
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        // written to another file first, so a crash while saving does not ruin the last checkpoint
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        self.write_checkpoint(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn resume<P: AsRef<Path>>(path: P) -> Result<Environment, PersistenceError> {
+        Environment::read_checkpoint(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn set_auto_checkpoint<P: Into<PathBuf>>(&mut self, every: u32, path: P) {
+        self.auto_checkpoint = Some(AutoCheckpoint {
+            every,
+            path: path.into(),
+        });
+    }
+
+    pub fn save_auto_checkpoint(&self) {
+        // called by next_generation, a failed checkpoint does not stop the run
+        let auto = match &self.auto_checkpoint {
+            Some(auto) if auto.every > 0 && self.generation.is_multiple_of(auto.every) => auto,
+            _ => return,
+        };
+        if let Err(e) = self.checkpoint(&auto.path) {
+            println!("Could not save a checkpoint: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_checkpoint {
+    use super::super::selection::{SelectionConfig, TournamentSelection};
+    use super::super::task::{Aggregation, EvaluationConfig};
+    use super::*;
+    use rand::Rng;
+
+    fn environment() -> Environment {
+        let mut env = Environment::new(30, 3, 2);
+        env.selection = Box::new(TournamentSelection { size: 3 });
+        env.set_recurrent(true);
+        env.evaluation.episodes = 3;
+        env.evaluation.aggregation = Aggregation::TrimmedMean(0.25);
+        env.mutation.max_nodes = Some(40);
+        env.mutation.perturbation_power = 0.3;
+        for generation in 0..5 {
+            for (i, nn) in env.species.iter_mut().enumerate() {
+                nn.reward((i % 7) as f32 + generation as f32);
+            }
+            env.next_generation();
+        }
+        env
+    }
+
+    fn assert_same(env1: &mut Environment, env2: &mut Environment) {
+        assert_eq!(env1.species, env2.species);
+        assert_eq!(env1.generation, env2.generation);
+        assert_eq!(env1.innovation, env2.innovation);
+        assert_eq!(env1.species_list, env2.species_list);
+        assert_eq!(env1.speciation, env2.speciation);
+        assert_eq!(env1.next_species_id, env2.next_species_id);
+        assert_eq!(env1.selection.config(), env2.selection.config());
+        assert_eq!(env1.elitism, env2.elitism);
+        assert_eq!(env1.stagnation, env2.stagnation);
+        assert_eq!(env1.best_fitness, env2.best_fitness);
+        assert_eq!(env1.last_improved, env2.last_improved);
+        assert_eq!(env1.input_layer, env2.input_layer);
+        assert_eq!(env1.output_layer, env2.output_layer);
+        assert_eq!(env1.recurrent, env2.recurrent);
+        assert_eq!(env1.mutation, env2.mutation);
+        assert_eq!(env1.evaluation, env2.evaluation);
+        assert_eq!(env1.auto_checkpoint, env2.auto_checkpoint);
+        assert_eq!(env1.rng, env2.rng);
+
+        // the random numbers continue from the same place
+        assert_eq!(env1.rng.gen::<u64>(), env2.rng.gen::<u64>());
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut env = environment();
+        let mut bytes = vec![];
+        env.write_checkpoint(&mut bytes).unwrap();
+        let mut resumed = Environment::read_checkpoint(&mut &bytes[..]).unwrap();
+
+        assert_eq!(
+            resumed.selection.config(),
+            Some(SelectionConfig::Tournament { size: 3 })
+        );
+        assert_same(&mut env, &mut resumed);
+    }
+
+    fn run(env: &mut Environment, generations: std::ops::Range<u32>) {
+        for generation in generations {
+            for (i, nn) in env.species.iter_mut().enumerate() {
+                nn.reward((i % 5) as f32 + generation as f32);
+            }
+            env.next_generation();
+        }
+    }
+
+    #[test]
+    fn test_resume_continues_the_same_run() {
+        let mut env = Environment::with_seed(30, 3, 2, 11);
+        run(&mut env, 0..6);
+
+        let mut bytes = vec![];
+        env.write_checkpoint(&mut bytes).unwrap();
+        let mut resumed = Environment::read_checkpoint(&mut &bytes[..]).unwrap();
+
+        run(&mut env, 6..12);
+        run(&mut resumed, 6..12);
+        assert_same(&mut env, &mut resumed);
+    }
+
+    #[test]
+    fn test_read_invalid_checkpoint() {
+        let env = environment();
+        let mut bytes = vec![];
+        env.write_checkpoint(&mut bytes).unwrap();
+
+        let mut population = vec![];
+        write_population(&mut population, &env.species).unwrap();
+        assert!(matches!(
+            Environment::read_checkpoint(&mut &population[..]),
+            Err(PersistenceError::BadMagic)
+        ));
+
+        bytes[4] = CHECKPOINT_VERSION + 1;
+        assert!(matches!(
+            Environment::read_checkpoint(&mut &bytes[..]),
+            Err(PersistenceError::UnsupportedVersion(Some(6)))
+        ));
+
+        // versions before 4 saved another mutation config
+        bytes[4] = 3;
+        match Environment::read_checkpoint(&mut &bytes[..]) {
+            Err(e) => assert_eq!(e.to_string(), "unsupported format version 3"),
+            Ok(_) => panic!("a version 3 checkpoint should not be read"),
+        }
+    }
+
+    #[test]
+    fn test_read_version_4() {
+        // version 4 is version 5 without the evaluation config
+        let mut env = environment();
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.push(4);
+        bincode::serialize_into(&mut bytes, &env.generation).unwrap();
+        bincode::serialize_into(&mut bytes, &env.innovation).unwrap();
+        bincode::serialize_into(&mut bytes, &env.species_list).unwrap();
+        bincode::serialize_into(&mut bytes, &env.speciation).unwrap();
+        bincode::serialize_into(&mut bytes, &env.next_species_id).unwrap();
+        bincode::serialize_into(&mut bytes, &env.selection.config()).unwrap();
+        bincode::serialize_into(&mut bytes, &env.elitism).unwrap();
+        bincode::serialize_into(&mut bytes, &env.stagnation).unwrap();
+        bincode::serialize_into(&mut bytes, &env.best_fitness).unwrap();
+        bincode::serialize_into(&mut bytes, &env.last_improved).unwrap();
+        bincode::serialize_into(&mut bytes, &env.input_layer).unwrap();
+        bincode::serialize_into(&mut bytes, &env.output_layer).unwrap();
+        bincode::serialize_into(&mut bytes, &env.recurrent).unwrap();
+        bincode::serialize_into(&mut bytes, &env.mutation).unwrap();
+        bincode::serialize_into(&mut bytes, &env.rng).unwrap();
+        bincode::serialize_into(&mut bytes, &env.auto_checkpoint).unwrap();
+        write_population(&mut bytes, &env.species).unwrap();
+
+        let mut resumed = Environment::read_checkpoint(&mut &bytes[..]).unwrap();
+        assert_eq!(resumed.evaluation, EvaluationConfig::default());
+        env.evaluation = EvaluationConfig::default();
+        assert_same(&mut env, &mut resumed);
+    }
+
+    #[test]
+    fn test_auto_checkpoint() {
+        let path = std::env::temp_dir().join(format!("nn_test_{}.checkpoint", std::process::id()));
+        let mut env = Environment::new(10, 2, 2);
+        env.set_auto_checkpoint(3, &path);
+
+        env.next_generation();
+        assert!(!path.exists());
+        env.next_generation();
+
+        // saved when the generation is 3
+        let mut resumed = Environment::resume(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.generation, 3);
+        assert_same(&mut env, &mut resumed);
+    }
+}
@@ -1,16 +1,110 @@
-use super::super::helpers;
 use super::activation_functions::ActivationFunctions;
+use super::checkpoint::AutoCheckpoint;
+use super::error::NnError;
 use super::genes::{NodeGene, NodeType};
+use super::innovation::InnovationTracker;
+use super::mutation::MutationConfig;
 use super::nn::NN;
-use rand::Rng;
+use super::selection::{compare_fitness, SelectionStrategy, TruncationSelection};
+use super::species::{SpeciationConfig, Species, StagnationConfig};
+use super::task::{EvaluationConfig, Task};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct Environment {
+    // the whole population, the species they belong to are in species_list
+    // a checkpoint saves it in the binary format, after the rest of the environment
+    #[serde(skip)]
     pub species: Vec<NN>,
     pub generation: u32,
+    pub innovation: InnovationTracker,
+    pub species_list: Vec<Species>,
+    pub speciation: SpeciationConfig,
+    pub next_species_id: u32,
+    // how the parents are picked inside a species
+    #[serde(with = "super::checkpoint::serde_selection")]
+    pub selection: Box<dyn SelectionStrategy>,
+    // the number of best genomes in every species copied unchanged to the next generation
+    pub elitism: usize,
+    pub stagnation: StagnationConfig,
+    // the best fitness in the population and the generation it was set
+    pub best_fitness: f32,
+    pub last_improved: u32,
+    // the layers every new network starts with
+    pub input_layer: Vec<NodeGene>,
+    pub output_layer: Vec<NodeGene>,
+    // new networks are made recurrent, see Environment::set_recurrent
+    pub recurrent: bool,
+    // the operators, rates and weight distributions used when a network is mutated
+    pub mutation: MutationConfig,
+    // how many episodes of a task every network plays, see Environment::evaluate
+    pub evaluation: EvaluationConfig,
+    // the random choices of the environment, it is saved in checkpoints
+    pub rng: ChaCha8Rng,
+    pub auto_checkpoint: Option<AutoCheckpoint>,
+    // the threads networks are evaluated on, 0 uses every core
+    // it is a setting of the machine, not of the run, so checkpoints do not save it
+    #[serde(skip)]
+    threads: usize,
+    #[serde(skip)]
+    pool: Option<ThreadPool>,
 }
 
 impl Environment {
     pub fn new(num_of_species: u32, num_input_layer: u32, num_output_layer: u32) -> Self {
+        Environment::with_rng(
+            num_of_species,
+            num_input_layer,
+            num_output_layer,
+            MutationConfig::default(),
+            ChaCha8Rng::from_entropy(),
+        )
+    }
+
+    pub fn with_seed(
+        num_of_species: u32,
+        num_input_layer: u32,
+        num_output_layer: u32,
+        seed: u64,
+    ) -> Self {
+        // the same seed gives the same population in every generation
+        Environment::with_config(
+            num_of_species,
+            num_input_layer,
+            num_output_layer,
+            MutationConfig::default(),
+            seed,
+        )
+    }
+
+    pub fn with_config(
+        num_of_species: u32,
+        num_input_layer: u32,
+        num_output_layer: u32,
+        mutation: MutationConfig,
+        seed: u64,
+    ) -> Self {
+        Environment::with_rng(
+            num_of_species,
+            num_input_layer,
+            num_output_layer,
+            mutation,
+            ChaCha8Rng::seed_from_u64(seed),
+        )
+    }
+
+    fn with_rng(
+        num_of_species: u32,
+        num_input_layer: u32,
+        num_output_layer: u32,
+        mutation: MutationConfig,
+        mut rng: ChaCha8Rng,
+    ) -> Self {
         let mut species = vec![];
         let mut input_layer = vec![];
         let mut id = 0;
@@ -21,7 +115,7 @@ impl Environment {
             output_layer.push(NodeGene::new(
                 id,
                 NodeType::Output,
-                helpers::lib::std0(),
+                mutation.weights.bias_init.sample(&mut rng),
                 ActivationFunctions::Identity,
                 0.0,
             ));
@@ -32,93 +126,422 @@ impl Environment {
             input_layer.push(NodeGene::new(
                 id,
                 NodeType::Input,
-                helpers::lib::std0(),
+                mutation.weights.bias_init.sample(&mut rng),
                 ActivationFunctions::Identity,
                 0.0,
             ));
             id += 1;
         }
 
+        // node ids after the input and output layer are handed out by the tracker
+        let mut innovation = InnovationTracker::new(id);
         for _ in 0..num_of_species {
-            species.push(NN::new(input_layer.clone(), output_layer.clone()))
+            species.push(NN::new(
+                input_layer.clone(),
+                output_layer.clone(),
+                &mut innovation,
+                &mutation.weights,
+                &mut rng,
+            ))
         }
 
-        Environment {
-            species: species,
+        let mut env = Environment {
+            species,
             generation: 1,
+            innovation,
+            species_list: vec![],
+            speciation: SpeciationConfig::default(),
+            next_species_id: 0,
+            selection: Box::new(TruncationSelection { fraction: 0.5 }),
+            elitism: 1,
+            stagnation: StagnationConfig::default(),
+            best_fitness: f32::NEG_INFINITY,
+            last_improved: 1,
+            input_layer,
+            output_layer,
+            recurrent: false,
+            mutation,
+            evaluation: EvaluationConfig::default(),
+            rng,
+            auto_checkpoint: None,
+            threads: 0,
+            pool: None,
+        };
+        env.speciate();
+        env
+    }
+
+    pub fn set_recurrent(&mut self, recurrent: bool) {
+        // recurrent networks can evolve connections backwards and remember between updates
+        self.recurrent = recurrent;
+        for nn in self.species.iter_mut() {
+            nn.recurrent = recurrent;
+            nn.invalidate_evaluation_order();
         }
     }
 
-    pub fn next_generation(&mut self, local_maximum: bool) {
+    pub fn next_generation(&mut self) {
+        if self.species.is_empty() {
+            return;
+        }
         self.generation += 1;
-        let mut nn: NN;
-        if local_maximum {
-            // if it has a local maximum, then get a random specie
-            // the local maximum is desided by the user/variable
-            let mut rng = rand::thread_rng();
-            let num = rng.gen_range(0..self.species.len());
-            nn = NN::clone(&self.species[num]);
-            let i = rng.gen_range(1..4);
-            for _ in 0..i {
-                nn.mutate();
-            }
+        self.innovation.next_generation();
+        self.speciate();
+        self.update_stagnation();
+
+        if self.generation - self.last_improved > self.stagnation.population_stagnation {
+            self.restart();
         } else {
-            nn = self.get_best_specie();
+            self.reproduce();
+        }
+        self.save_auto_checkpoint();
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        // the pool is made again with the new number of threads when it is needed
+        self.threads = threads;
+        self.pool = None;
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn evaluate(&mut self, task: &dyn Task) {
+        // the fitness of every network is set by the task, instead of collected with NN::reward
+        // the networks are scored on their own, so they are spread over the threads and
+        // the result is the same for any number of threads
+        // the elites are scored again on the new episodes as well, so a network that was
+        // lucky once does not stay the champion
+        let seeds: Vec<u64> = (0..self.evaluation.episodes.max(1))
+            .map(|_| self.rng.gen())
+            .collect();
+        let aggregation = self.evaluation.aggregation;
+        if self.pool.is_none() {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(self.threads)
+                .build()
+                .expect("could not start the evaluation threads");
+            self.pool = Some(pool);
         }
-        // want to set the fitness to 0 again when new generation
-        nn.fitness = 0.0;
+        let species = &mut self.species;
+        self.pool.as_ref().unwrap().install(|| {
+            species.par_iter_mut().for_each(|nn| {
+                let scores = seeds
+                    .iter()
+                    .map(|seed| {
+                        nn.reset_state();
+                        task.evaluate(nn, *seed)
+                    })
+                    .collect();
+                nn.fitness = aggregation.aggregate(scores);
+            })
+        });
+    }
+
+    pub fn evolve(&mut self, task: &dyn Task, generations: u32) -> Result<(), NnError> {
+        self.check_task(task)?;
+        for _ in 0..generations {
+            self.evaluate(task);
+            self.next_generation();
+        }
+        Ok(())
+    }
+
+    pub fn check_task(&self, task: &dyn Task) -> Result<(), NnError> {
+        // the networks have to fit the task
+        if task.input_size() != self.input_layer.len() {
+            return Err(NnError::InputSizeMismatch {
+                expected: self.input_layer.len(),
+                got: task.input_size(),
+            });
+        }
+        if task.output_size() != self.output_layer.len() {
+            return Err(NnError::OutputSizeMismatch {
+                expected: self.output_layer.len(),
+                got: task.output_size(),
+            });
+        }
+        Ok(())
+    }
+
+    fn reproduce(&mut self) {
+        self.remove_stagnant_species();
+        self.share_fitness();
+
+        let offspring = self.offspring_per_species();
+
         let mut new_species = vec![];
-        let mut rng = rand::thread_rng();
-        for _ in 0..self.species.len() - 1 {
-            let mut new_nn = NN::clone(&nn);
-            // mutate a random number of times (up to 3)
-            let i = rng.gen_range(1..3);
-            for _ in 0..i {
-                new_nn.mutate();
+        for (specie, num_offspring) in self.species_list.iter_mut().zip(&offspring) {
+            let mut members = specie.members.clone();
+            members.sort_by(|a, b| {
+                compare_fitness(
+                    self.species[*b].calculate_fitness(),
+                    self.species[*a].calculate_fitness(),
+                )
+            });
+
+            // the next generation is compared to a random member of this generation
+            specie.representative =
+                NN::clone(&self.species[*members.choose(&mut self.rng).unwrap()]);
+            specie.members.clear();
+
+            if *num_offspring == 0 {
+                continue;
+            }
+
+            // the champions of the species are kept as they are
+            // want to set the fitness to 0 again when new generation
+            let elites = self.elitism.min(*num_offspring).min(members.len());
+            for i in &members[..elites] {
+                let mut champion = NN::clone(&self.species[*i]);
+                champion.fitness = 0.0;
+                new_species.push(champion);
+            }
+
+            let num_children = *num_offspring - elites;
+            let fitness: Vec<f32> = members
+                .iter()
+                .map(|i| self.species[*i].calculate_fitness())
+                .collect();
+            let parents: Vec<usize> = self
+                .selection
+                .select(&fitness, num_children * 2, &mut self.rng)
+                .iter()
+                .map(|i| members[*i])
+                .collect();
+            for pair in parents.chunks(2) {
+                let parent1 = &self.species[pair[0]];
+                let parent2 = &self.species[pair[1]];
+                let mut new_nn = NN::crossover(parent1, parent2, &mut self.rng);
+                new_nn.mutate(&mut self.innovation, &self.mutation, &mut self.rng);
+                new_species.push(new_nn);
             }
-            new_species.push(new_nn);
         }
-        new_species.push(NN::clone(&nn));
+
+        // species without offspring die out
+        let mut i = 0;
+        self.species_list.retain(|_| {
+            i += 1;
+            offspring[i - 1] > 0
+        });
 
         self.species = new_species;
     }
 
-    pub fn get_best_specie(&mut self) -> NN {
-        // NOTE: Now is the fittest only used
+    pub fn speciate(&mut self) {
+        // every genome joins the first species it is compatible with,
+        // or it starts a new species
+        for specie in self.species_list.iter_mut() {
+            specie.members.clear();
+        }
 
-        // find the fittest specie
-        // it only checks the fitness
-        // the fitness should be a function that takes number of nodes and connections in
-        // consideration
+        for (i, nn) in self.species.iter().enumerate() {
+            let compatible = self.species_list.iter_mut().find(|specie| {
+                NN::compatibility_distance(nn, &specie.representative, &self.speciation)
+                    < self.speciation.compatibility_threshold
+            });
+            match compatible {
+                Some(specie) => specie.members.push(i),
+                None => {
+                    self.species_list.push(Species::new(
+                        self.next_species_id,
+                        NN::clone(nn),
+                        i,
+                        self.generation,
+                    ));
+                    self.next_species_id += 1;
+                }
+            }
+        }
 
-        let mut fittest: &NN = &NN::clone(&self.species[0]);
-        let mut next_fittest: &NN = &NN::clone(&self.species[0]);
+        self.species_list
+            .retain(|specie| !specie.members.is_empty());
 
-        for specie in &self.species {
-            // check if it is fitter than the fittest
-            // if it is, push fittest to next fittest
-            if specie.calculate_fitness() > fittest.calculate_fitness() {
-                next_fittest = fittest;
-                fittest = specie;
-                continue;
+        // move the threshold towards the number of species we want
+        let config = &mut self.speciation;
+        if self.species_list.len() > config.target_species {
+            config.compatibility_threshold += config.threshold_step;
+        } else if self.species_list.len() < config.target_species {
+            config.compatibility_threshold =
+                (config.compatibility_threshold - config.threshold_step).max(config.min_threshold);
+        }
+    }
+
+    pub fn update_stagnation(&mut self) {
+        // keeps track of the best fitness of every species and the whole population
+        for specie in self.species_list.iter_mut() {
+            let best = specie
+                .members
+                .iter()
+                .map(|i| self.species[*i].calculate_fitness())
+                .fold(f32::NEG_INFINITY, f32::max);
+            specie.update_fitness(best, self.generation);
+
+            if best > self.best_fitness {
+                self.best_fitness = best;
+                self.last_improved = self.generation;
             }
+        }
+    }
 
-            // if not fitter than the fittest, compare to the next fittest
+    pub fn remove_stagnant_species(&mut self) {
+        // the best species are protected, the others are removed when they have not
+        // improved for a while so the population is spent on species that do
+        let mut order: Vec<usize> = (0..self.species_list.len()).collect();
+        order.sort_by(|a, b| {
+            compare_fitness(
+                self.species_list[*b].best_fitness,
+                self.species_list[*a].best_fitness,
+            )
+        });
+        let generation = self.generation;
+        let max_stagnation = self.stagnation.max_stagnation;
 
-            if specie.calculate_fitness() > next_fittest.calculate_fitness() {
-                next_fittest = specie;
-                continue;
+        // removing every species would remove the whole population, so the best is kept
+        let mut keep = self.stagnation.species_elitism;
+        if self
+            .species_list
+            .iter()
+            .all(|specie| specie.is_stagnant(generation, max_stagnation))
+        {
+            keep = keep.max(1);
+        }
+        let protected = &order[..keep.min(order.len())];
+
+        let mut i = 0;
+        self.species_list.retain(|specie| {
+            i += 1;
+            protected.contains(&(i - 1)) || !specie.is_stagnant(generation, max_stagnation)
+        });
+    }
+
+    pub fn restart(&mut self) {
+        // the whole population has stagnated, the champions of the best species are kept
+        // and the rest of the population starts over with new networks
+        let population = self.species.len();
+        self.species_list
+            .sort_by(|a, b| compare_fitness(b.best_fitness, a.best_fitness));
+        self.species_list.truncate(self.stagnation.species_elitism);
+
+        let mut new_species = vec![];
+        for specie in self.species_list.iter_mut() {
+            let champion = specie
+                .members
+                .iter()
+                .max_by(|a, b| {
+                    compare_fitness(
+                        self.species[**a].calculate_fitness(),
+                        self.species[**b].calculate_fitness(),
+                    )
+                })
+                .unwrap();
+            let mut nn = NN::clone(&self.species[*champion]);
+            nn.fitness = 0.0;
+            new_species.push(nn);
+
+            specie.members.clear();
+            specie.last_improved = self.generation;
+        }
+        new_species.truncate(population);
+
+        while new_species.len() < population {
+            let mut nn = NN::new(
+                self.input_layer.clone(),
+                self.output_layer.clone(),
+                &mut self.innovation,
+                &self.mutation.weights,
+                &mut self.rng,
+            );
+            nn.recurrent = self.recurrent;
+            new_species.push(nn);
+        }
+
+        self.species = new_species;
+        self.best_fitness = f32::NEG_INFINITY;
+        self.last_improved = self.generation;
+    }
+
+    pub fn share_fitness(&mut self) -> Vec<f32> {
+        // explicit fitness sharing: the fitness of a genome is divided by the size of its
+        // species, so one big species can not take over before new structures are optimized
+        // the fitness is shifted to be positive first because the rewards can be negative
+        let min_fitness = self
+            .species
+            .iter()
+            .map(|nn| nn.calculate_fitness())
+            .fold(f32::INFINITY, f32::min);
+
+        let mut shared_fitness = vec![0.0; self.species.len()];
+        for specie in self.species_list.iter_mut() {
+            specie.adjusted_fitness = 0.0;
+            for i in &specie.members {
+                // a NaN fitness shares nothing
+                shared_fitness[*i] = ((self.species[*i].calculate_fitness() - min_fitness)
+                    / specie.members.len() as f32)
+                    .max(0.0);
+                specie.adjusted_fitness += shared_fitness[*i];
             }
         }
+        shared_fitness
+    }
+
+    fn offspring_per_species(&self) -> Vec<usize> {
+        // every species gets offspring in proportion to its adjusted fitness
+        let population = self.species.len();
+        if self.species_list.is_empty() {
+            return vec![];
+        }
+        let totals: Vec<f32> = self
+            .species_list
+            .iter()
+            .map(|specie| specie.adjusted_fitness)
+            .collect();
+        let sum: f32 = totals.iter().sum();
 
-        // if two species has the same fitness, merge the two species
-        // NN::merge(fittest, next_fittest)
+        let mut offspring: Vec<usize> = totals
+            .iter()
+            .map(|total| {
+                if sum > 0.0 {
+                    (total / sum * population as f32).floor() as usize
+                } else {
+                    population / totals.len()
+                }
+            })
+            .collect();
+
+        // what is left after rounding goes to the fittest species
+        let mut order: Vec<usize> = (0..totals.len()).collect();
+        order.sort_by(|a, b| compare_fitness(totals[*b], totals[*a]));
+        let mut assigned: usize = offspring.iter().sum();
+        let mut i = 0;
+        while assigned < population {
+            offspring[order[i % order.len()]] += 1;
+            assigned += 1;
+            i += 1;
+        }
+        offspring
+    }
+
+    pub fn get_best_specie(&self) -> NN {
+        // the fittest network, the first one when several are as fit
+        let mut fittest = &self.species[0];
+        for nn in &self.species {
+            if compare_fitness(nn.calculate_fitness(), fittest.calculate_fitness()).is_gt() {
+                fittest = nn;
+            }
+        }
         NN::clone(fittest)
     }
 }
 
 #[cfg(test)]
 mod test_environment {
+    use super::super::super::helpers::distributions::Distribution;
+    use super::super::genes::ConnectionGene;
+    use super::super::mutation::WeightConfig;
+    use super::super::selection::{RankSelection, RouletteSelection, TournamentSelection};
+    use super::super::task::Aggregation;
     use super::*;
 
     #[test]
@@ -152,7 +575,13 @@ mod test_environment {
             0.0,
         )];
 
-        let mut nn1 = NN::new(input_layer, output_layer);
+        let mut nn1 = NN::new(
+            input_layer,
+            output_layer,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
         let mut nn2 = NN::clone(&nn1);
         let mut nn3 = NN::clone(&nn1);
         let mut nn4 = NN::clone(&nn1);
@@ -182,4 +611,480 @@ mod test_environment {
         // TODO: add a test for species with same fintness but different complexity
         // want to choose the specie with least nodes!
     }
+
+    #[test]
+    fn test_next_generation() {
+        let mut env = Environment::new(10, 2, 2);
+        env.species[3].fitness = 5.0;
+        let fittest = NN::clone(&env.species[3]);
+        env.next_generation();
+
+        assert_eq!(env.species.len(), 10);
+        assert_eq!(env.generation, 2);
+        // the fittest is kept as it is
+        assert!(env
+            .species
+            .iter()
+            .any(|nn| nn.connection_genes == fittest.connection_genes));
+    }
+
+    #[test]
+    fn test_next_generation_selection() {
+        let mut env = Environment::new(10, 2, 2);
+        env.selection = Box::new(RouletteSelection);
+        env.elitism = 3;
+        env.species[3].fitness = 5.0;
+        env.next_generation();
+
+        assert_eq!(env.species.len(), 10);
+
+        // without elitism there is still a full population
+        env.elitism = 0;
+        env.selection = Box::new(TournamentSelection { size: 3 });
+        env.next_generation();
+        assert_eq!(env.species.len(), 10);
+    }
+
+    #[test]
+    fn test_set_recurrent() {
+        let mut env = Environment::new(3, 2, 2);
+        env.set_recurrent(true);
+
+        assert!(env.species.iter().all(|nn| nn.recurrent));
+
+        // networks made after a restart are recurrent as well
+        env.stagnation.species_elitism = 0;
+        env.restart();
+        assert!(env.species.iter().all(|nn| nn.recurrent));
+    }
+
+    #[test]
+    fn test_speciate() {
+        let mut env = Environment::new(4, 2, 2);
+        env.speciation.target_species = 2;
+
+        // all the new networks have the same structure
+        assert_eq!(env.species_list.len(), 1);
+        assert_eq!(env.species_list[0].members, vec![0, 1, 2, 3]);
+
+        // a network with a lot of new genes is not compatible anymore
+        for i in 10..20 {
+            let conn = ConnectionGene::new(
+                i,
+                i,
+                0.0,
+                -1,
+                env.innovation.get_connection_innovation(i, i),
+            );
+            env.species[3].connection_genes.push(conn);
+        }
+        let threshold = env.speciation.compatibility_threshold;
+        env.speciate();
+
+        assert_eq!(env.species_list.len(), 2);
+        assert_eq!(env.species_list[0].members, vec![0, 1, 2]);
+        assert_eq!(env.species_list[1].members, vec![3]);
+        // the number of species was right, so the threshold did not change
+        assert_eq!(env.speciation.compatibility_threshold, threshold);
+
+        env.speciation.target_species = 5;
+        env.speciate();
+        assert_eq!(
+            env.speciation.compatibility_threshold,
+            threshold - env.speciation.threshold_step
+        );
+    }
+
+    #[test]
+    fn test_share_fitness() {
+        let mut env = Environment::new(3, 2, 2);
+        for i in 10..20 {
+            let conn = ConnectionGene::new(
+                i,
+                i,
+                0.0,
+                -1,
+                env.innovation.get_connection_innovation(i, i),
+            );
+            env.species[2].connection_genes.push(conn);
+        }
+        env.speciate();
+
+        env.species[0].fitness = 10.0;
+        env.species[1].fitness = 20.0;
+        env.species[2].fitness = 50.0;
+        let shift = env.species[0].calculate_fitness();
+
+        let shared_fitness = env.share_fitness();
+
+        // the fitness is shifted so the worst is 0 and divided by the size of the species
+        assert_eq!(shared_fitness[0], 0.0);
+        assert_eq!(shared_fitness[1], 5.0);
+        assert_eq!(
+            shared_fitness[2],
+            env.species[2].calculate_fitness() - shift
+        );
+        assert_eq!(env.species_list[0].adjusted_fitness, 5.0);
+    }
+
+    #[test]
+    fn test_offspring_per_species() {
+        let mut env = Environment::new(10, 2, 2);
+        let mut other = Species::new(1, NN::clone(&env.species[0]), 9, 1);
+        env.species_list[0].members.pop();
+        env.species_list[0].adjusted_fitness = 3.0;
+        other.adjusted_fitness = 1.0;
+        env.species_list.push(other);
+
+        // the offspring always fill the population
+        assert_eq!(env.offspring_per_species(), vec![8, 2]);
+
+        env.species_list[1].adjusted_fitness = 0.0;
+        env.species_list[0].adjusted_fitness = 0.0;
+        assert_eq!(env.offspring_per_species(), vec![5, 5]);
+    }
+
+    #[test]
+    fn test_remove_stagnant_species() {
+        let mut env = Environment::new(3, 2, 2);
+        env.stagnation.species_elitism = 1;
+        env.stagnation.max_stagnation = 5;
+        env.species_list = vec![];
+        for i in 0..3 {
+            let mut specie = Species::new(i, NN::clone(&env.species[0]), i as usize, 1);
+            specie.best_fitness = i as f32;
+            env.species_list.push(specie);
+        }
+        env.species_list[1].last_improved = 8;
+        env.generation = 10;
+
+        env.remove_stagnant_species();
+
+        // the best species is protected and species 1 improved recently
+        let ids: Vec<u32> = env.species_list.iter().map(|specie| specie.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_remove_every_stagnant_species() {
+        let mut env = Environment::new(6, 2, 2);
+        env.stagnation.species_elitism = 0;
+        env.stagnation.max_stagnation = 2;
+        env.species_list = vec![];
+        for i in 0..3 {
+            let mut specie = Species::new(i, NN::clone(&env.species[0]), i as usize, 1);
+            specie.best_fitness = i as f32;
+            env.species_list.push(specie);
+        }
+        env.generation = 10;
+
+        // every species is stagnant, the best one is kept instead of the population dying out
+        env.remove_stagnant_species();
+        let ids: Vec<u32> = env.species_list.iter().map(|specie| specie.id).collect();
+        assert_eq!(ids, vec![2]);
+
+        env.species_list.clear();
+        assert!(env.offspring_per_species().is_empty());
+
+        // the whole population stagnates before the population stagnation restarts it
+        let mut env = Environment::new(10, 2, 2);
+        env.stagnation.species_elitism = 0;
+        env.stagnation.max_stagnation = 0;
+        env.stagnation.population_stagnation = 100;
+        // without mutations the networks stay the same and nothing improves
+        env.mutation.probabilities = vec![];
+        for _ in 0..5 {
+            env.next_generation();
+            assert_eq!(env.species.len(), 10);
+        }
+    }
+
+    #[test]
+    fn test_update_stagnation() {
+        let mut env = Environment::new(2, 2, 2);
+        env.species[0].fitness = 100.0;
+        env.update_stagnation();
+
+        assert_eq!(env.best_fitness, env.species[0].calculate_fitness());
+        assert_eq!(env.species_list[0].best_fitness, env.best_fitness);
+        assert_eq!(env.species_list[0].fitness_history.len(), 1);
+        assert_eq!(env.last_improved, 1);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut env = Environment::new(10, 2, 2);
+        env.stagnation.population_stagnation = 3;
+        env.species[4].fitness = 50.0;
+        let champion = NN::clone(&env.species[4]);
+
+        // nothing has come close to the best fitness for a while
+        env.best_fitness = 1000.0;
+        env.generation += 3;
+        env.next_generation();
+
+        assert_eq!(env.species.len(), 10);
+        assert_eq!(env.species[0].connection_genes, champion.connection_genes);
+        assert_eq!(env.last_improved, env.generation);
+        assert!(env.species_list.len() <= env.stagnation.species_elitism);
+    }
+
+    fn reward_weights(env: &mut Environment) {
+        // a fitness that only depends on the genomes
+        for nn in env.species.iter_mut() {
+            let reward = nn.connection_genes.iter().map(|conn| conn.weight).sum();
+            nn.reward(reward);
+        }
+    }
+
+    #[test]
+    fn test_with_config() {
+        let weights = WeightConfig {
+            weight_init: Distribution::Uniform {
+                low: 3.0,
+                high: 4.0,
+            },
+            bias_init: Distribution::TruncatedNormal {
+                mean: 0.0,
+                std_dev: 1.0,
+                low: -0.1,
+                high: 0.1,
+            },
+            ..WeightConfig::default()
+        };
+        let mutation = MutationConfig {
+            weights,
+            max_nodes: Some(6),
+            ..MutationConfig::default()
+        };
+        let mut env = Environment::with_config(5, 2, 2, mutation.clone(), 3);
+
+        assert_eq!(env.mutation, mutation);
+        for nn in &env.species {
+            assert!(nn
+                .connection_genes
+                .iter()
+                .all(|conn| conn.weight >= 3.0 && conn.weight < 4.0));
+            assert!(nn.node_genes.values().all(|node| node.bias.abs() <= 0.1));
+        }
+
+        for _ in 0..20 {
+            env.next_generation();
+        }
+        assert!(env.species.iter().all(|nn| nn.node_genes.len() <= 6));
+    }
+
+    // a task where the best network has the biggest weights
+    struct WeightTask;
+
+    impl Task for WeightTask {
+        fn input_size(&self) -> usize {
+            3
+        }
+
+        fn output_size(&self) -> usize {
+            2
+        }
+
+        fn evaluate(&self, nn: &mut NN, _seed: u64) -> f32 {
+            nn.connection_genes.iter().map(|conn| conn.weight).sum()
+        }
+    }
+
+    #[test]
+    fn test_evolve() {
+        let mut env = Environment::with_seed(30, 3, 2, 4);
+        env.evolve(&WeightTask, 1).unwrap();
+        assert_eq!(env.generation, 2);
+        let first = env.best_fitness;
+
+        env.evolve(&WeightTask, 20).unwrap();
+        assert_eq!(env.generation, 22);
+        assert!(env.best_fitness > first);
+
+        let mut wrong_size = Environment::with_seed(10, 2, 2, 4);
+        assert_eq!(
+            wrong_size.evolve(&WeightTask, 1),
+            Err(NnError::InputSizeMismatch {
+                expected: 2,
+                got: 3
+            })
+        );
+        let mut wrong_size = Environment::with_seed(10, 3, 1, 4);
+        assert_eq!(
+            wrong_size.evolve(&WeightTask, 1),
+            Err(NnError::OutputSizeMismatch {
+                expected: 1,
+                got: 2
+            })
+        );
+        assert_eq!(wrong_size.generation, 1);
+    }
+
+    // a task that activates the network, so the evaluation takes a while
+    struct ActivationTask;
+
+    impl Task for ActivationTask {
+        fn input_size(&self) -> usize {
+            3
+        }
+
+        fn output_size(&self) -> usize {
+            2
+        }
+
+        fn evaluate(&self, nn: &mut NN, _seed: u64) -> f32 {
+            (0..50)
+                .map(|i| nn.activate(&[i as f32 / 50.0, 1.0, -0.5])[0])
+                .sum()
+        }
+    }
+
+    #[test]
+    fn test_threads_give_the_same_run() {
+        let mut runs = vec![];
+        for threads in [1, 2, 5] {
+            let mut env = Environment::with_seed(60, 3, 2, 9);
+            env.set_recurrent(true);
+            env.set_threads(threads);
+            assert_eq!(env.threads(), threads);
+            env.evolve(&ActivationTask, 8).unwrap();
+            env.evaluate(&ActivationTask);
+            // compared as bytes, a fitness can be NaN
+            let bytes: Vec<Vec<u8>> = env
+                .species
+                .iter()
+                .map(|nn| nn.to_bytes().unwrap())
+                .collect();
+            runs.push(bytes);
+        }
+        assert_eq!(runs[0], runs[1]);
+        assert_eq!(runs[0], runs[2]);
+    }
+
+    // a task that gives some of the networks a NaN fitness
+    struct NanTask;
+
+    impl Task for NanTask {
+        fn input_size(&self) -> usize {
+            3
+        }
+
+        fn output_size(&self) -> usize {
+            2
+        }
+
+        fn evaluate(&self, nn: &mut NN, _seed: u64) -> f32 {
+            let weight = nn.connection_genes[0].weight;
+            if weight < 0.0 {
+                f32::NAN
+            } else {
+                weight
+            }
+        }
+    }
+
+    #[test]
+    fn test_nan_fitness() {
+        let mut env = Environment::with_seed(40, 3, 2, 12);
+        env.selection = Box::new(RankSelection);
+        env.evolve(&NanTask, 5).unwrap();
+        env.stagnation.population_stagnation = 0;
+        env.evolve(&NanTask, 2).unwrap();
+        assert_eq!(env.generation, 8);
+        assert_eq!(env.species.len(), 40);
+    }
+
+    // a task where the fitness is only the seed, it keeps the seeds it is given
+    struct SeedTask {
+        seeds: std::sync::Mutex<Vec<u64>>,
+    }
+
+    impl Task for SeedTask {
+        fn input_size(&self) -> usize {
+            3
+        }
+
+        fn output_size(&self) -> usize {
+            2
+        }
+
+        fn evaluate(&self, _nn: &mut NN, seed: u64) -> f32 {
+            self.seeds.lock().unwrap().push(seed);
+            (seed % 1000) as f32
+        }
+    }
+
+    #[test]
+    fn test_evaluate_episodes() {
+        let mut env = Environment::with_seed(20, 3, 2, 6);
+        env.evaluation = EvaluationConfig {
+            episodes: 4,
+            aggregation: Aggregation::Min,
+        };
+        let task = SeedTask {
+            seeds: std::sync::Mutex::new(vec![]),
+        };
+
+        let mut last_seeds: Vec<u64> = vec![];
+        for _ in 0..3 {
+            env.evaluate(&task);
+            let mut seeds = task.seeds.lock().unwrap().split_off(0);
+            assert_eq!(seeds.len(), 4 * 20);
+            seeds.sort();
+            seeds.dedup();
+            // every network plays the same episodes
+            assert_eq!(seeds.len(), 4);
+            let min = seeds
+                .iter()
+                .map(|seed| (seed % 1000) as f32)
+                .fold(f32::INFINITY, f32::min);
+            assert!(env.species.iter().all(|nn| nn.fitness == min));
+
+            // the next generation, and the elites in it, gets new episodes
+            assert!(seeds.iter().all(|seed| !last_seeds.contains(seed)));
+            last_seeds = seeds;
+            env.next_generation();
+        }
+    }
+
+    #[test]
+    fn test_seeded_runs_are_identical() {
+        let mut env1 = Environment::with_seed(30, 3, 2, 7);
+        let mut env2 = Environment::with_seed(30, 3, 2, 7);
+        env1.set_recurrent(true);
+        env2.set_recurrent(true);
+
+        for _ in 0..15 {
+            assert_eq!(env1.species, env2.species);
+            reward_weights(&mut env1);
+            reward_weights(&mut env2);
+            env1.next_generation();
+            env2.next_generation();
+        }
+        assert_eq!(env1.species, env2.species);
+        assert_eq!(env1.innovation, env2.innovation);
+        assert_eq!(env1.species_list, env2.species_list);
+
+        // another seed gives another population
+        let env3 = Environment::with_seed(30, 3, 2, 8);
+        assert_ne!(Environment::with_seed(30, 3, 2, 7).species, env3.species);
+    }
+
+    #[test]
+    fn test_environment_innovation() {
+        let env = Environment::new(2, 2, 2);
+
+        // the first free node id comes after the input and output layer
+        assert_eq!(env.innovation.next_node_id, 4);
+
+        // every network starts with the same innovation numbers
+        let innovations = |nn: &NN| {
+            nn.connection_genes
+                .iter()
+                .map(|conn| conn.innovation)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(innovations(&env.species[0]), innovations(&env.species[1]));
+    }
 }
@@ -0,0 +1,69 @@
+use std::fmt;
+
+// What can go wrong when a network is used, so a broken genome can be
+// thrown away instead of crashing the whole run
+#[derive(Debug, PartialEq, Clone)]
+pub enum NnError {
+    // the number of inputs is not the number of nodes in the input layer
+    InputSizeMismatch { expected: usize, got: usize },
+    // the number of outputs a task wants is not the number of nodes in the output layer
+    OutputSizeMismatch { expected: usize, got: usize },
+    // less than two layers, or a layer without nodes
+    EmptyNetwork,
+    // a connection to or from a node the network does not have
+    DanglingConnection { from: u32, to: u32 },
+    // a node in node_genes_network or a gater that is not in node_genes
+    MissingNode(u32),
+    // a node in node_genes that is not in exactly one layer
+    MisplacedNode(u32),
+    // two connections between the same nodes
+    DuplicateConnection { from: u32, to: u32 },
+    // a connection going into an input node
+    InputHasIncoming(u32),
+    // a connection going out of an output node in a feed forward network
+    OutputHasOutgoing(u32),
+    // some nodes can not be evaluated because they depend on each other
+    CycleDetected,
+}
+
+impl fmt::Display for NnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NnError::InputSizeMismatch { expected, got } => {
+                write!(f, "expected {} inputs, got {}", expected, got)
+            }
+            NnError::OutputSizeMismatch { expected, got } => {
+                write!(f, "expected {} outputs, got {}", expected, got)
+            }
+            NnError::EmptyNetwork => write!(f, "the network has an empty layer"),
+            NnError::DanglingConnection { from, to } => {
+                write!(f, "the connection {} -> {} has a missing node", from, to)
+            }
+            NnError::MissingNode(id) => write!(f, "node {} does not exist", id),
+            NnError::MisplacedNode(id) => write!(f, "node {} is not in exactly one layer", id),
+            NnError::DuplicateConnection { from, to } => {
+                write!(f, "more than one connection {} -> {}", from, to)
+            }
+            NnError::InputHasIncoming(id) => write!(f, "input node {} has an input", id),
+            NnError::OutputHasOutgoing(id) => write!(f, "output node {} has an output", id),
+            NnError::CycleDetected => write!(f, "the network has a cycle"),
+        }
+    }
+}
+
+impl std::error::Error for NnError {}
+
+#[cfg(test)]
+mod test_error {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = NnError::InputSizeMismatch {
+            expected: 2,
+            got: 3,
+        };
+        assert_eq!(error.to_string(), "expected 2 inputs, got 3");
+        assert_eq!(NnError::MissingNode(4).to_string(), "node 4 does not exist");
+    }
+}
@@ -1,36 +1,47 @@
 use super::activation_functions::ActivationFunctions;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum NodeType {
     Input,
     Hidden,
     Output,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct NodeGene {
     pub id: u32,
     pub node_type: NodeType,
     pub bias: f32,
     pub squash: ActivationFunctions,
+    // the sum of the inputs while the node is evaluated, it is state and not saved genes
+    #[serde(default)]
     pub value: f32,
+    // the output from the last time the node was evaluated, used by recurrent connections
+    #[serde(default)]
+    pub activation: f32,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct ConnectionGene {
     pub from: u32, // from node
     pub to: u32,   // to node
     pub weight: f32,
     pub gater: i32,
+    pub innovation: u32, // historical marking used to align genomes
+    // a disabled connection is skipped when the network is evaluated, but keeps its history
+    pub enabled: bool,
 }
 
 impl ConnectionGene {
-    pub fn new(from: u32, to: u32, weight: f32, gater: i32) -> Self {
+    pub fn new(from: u32, to: u32, weight: f32, gater: i32, innovation: u32) -> Self {
         ConnectionGene {
-            from: from,
-            to: to,
-            weight: weight,
-            gater: gater,
+            from,
+            to,
+            weight,
+            gater,
+            innovation,
+            enabled: true,
         }
     }
 }
@@ -44,11 +55,12 @@ impl NodeGene {
         value: f32,
     ) -> Self {
         NodeGene {
-            id: id,
-            node_type: node_type,
-            bias: bias,
-            squash: squash,
-            value: value,
+            id,
+            node_type,
+            bias,
+            squash,
+            value,
+            activation: 0.0,
         }
     }
 
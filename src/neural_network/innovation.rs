@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Hands out node ids and connection innovation numbers for the whole population.
+// The same structural mutation gets the same numbers in every network, so genomes
+// can be lined up against each other
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InnovationTracker {
+    pub next_node_id: u32,
+    pub next_innovation: u32,
+    // (from, to) -> innovation number, kept for the whole run
+    pub connections: HashMap<(u32, u32), u32>,
+    // (from, to) of a split connection -> id of the new node, kept for one generation
+    pub splits: HashMap<(u32, u32), u32>,
+}
+
+impl InnovationTracker {
+    pub fn new(next_node_id: u32) -> Self {
+        InnovationTracker {
+            next_node_id,
+            next_innovation: 0,
+            connections: HashMap::new(),
+            splits: HashMap::new(),
+        }
+    }
+
+    pub fn get_connection_innovation(&mut self, from: u32, to: u32) -> u32 {
+        match self.connections.get(&(from, to)) {
+            Some(innovation) => *innovation,
+            None => {
+                let innovation = self.next_innovation;
+                self.next_innovation += 1;
+                self.connections.insert((from, to), innovation);
+                innovation
+            }
+        }
+    }
+
+    pub fn get_split_node_id(&mut self, from: u32, to: u32) -> u32 {
+        // every network that puts a node between from and to in this generation
+        // gets the same node id
+        match self.splits.get(&(from, to)) {
+            Some(id) => *id,
+            None => {
+                let id = self.new_node_id();
+                self.splits.insert((from, to), id);
+                id
+            }
+        }
+    }
+
+    pub fn new_node_id(&mut self) -> u32 {
+        let id = self.next_node_id;
+        self.next_node_id += 1;
+        id
+    }
+
+    pub fn next_generation(&mut self) {
+        // the same split in a later generation is a new innovation
+        self.splits.clear();
+    }
+}
+
+#[cfg(test)]
+mod test_innovation {
+    use super::*;
+
+    #[test]
+    fn test_get_connection_innovation() {
+        let mut innovation = InnovationTracker::new(0);
+
+        let first = innovation.get_connection_innovation(0, 1);
+        let second = innovation.get_connection_innovation(1, 0);
+
+        assert_ne!(first, second);
+        assert_eq!(innovation.get_connection_innovation(0, 1), first);
+
+        // connections keep their number across generations
+        innovation.next_generation();
+        assert_eq!(innovation.get_connection_innovation(1, 0), second);
+    }
+
+    #[test]
+    fn test_get_split_node_id() {
+        let mut innovation = InnovationTracker::new(2);
+
+        let id = innovation.get_split_node_id(0, 1);
+        assert_eq!(id, 2);
+        assert_eq!(innovation.get_split_node_id(0, 1), 2);
+        assert_eq!(innovation.get_split_node_id(1, 0), 3);
+
+        // a split in a new generation gets a new node
+        innovation.next_generation();
+        assert_eq!(innovation.get_split_node_id(0, 1), 4);
+    }
+
+    #[test]
+    fn test_new_node_id() {
+        let mut innovation = InnovationTracker::new(5);
+
+        assert_eq!(innovation.new_node_id(), 5);
+        assert_eq!(innovation.new_node_id(), 6);
+        assert_eq!(innovation.next_node_id, 7);
+    }
+}
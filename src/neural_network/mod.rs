@@ -1,4 +1,14 @@
 pub mod nn;
 pub mod activation_functions;
 pub mod genes;
-pub mod environment;
\ No newline at end of file
+pub mod environment;
+pub mod innovation;
+pub mod species;
+pub mod selection;
+pub mod error;
+pub mod persistence;
+pub mod binary;
+pub mod checkpoint;
+pub mod mutation;
+pub mod task;
+pub mod benchmark;
\ No newline at end of file
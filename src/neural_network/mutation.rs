@@ -0,0 +1,216 @@
+use super::super::helpers::distributions::{self, Distribution};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+// How the weights and biases are drawn when a gene is made and when it is mutated
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct WeightConfig {
+    pub weight_init: Distribution,
+    pub bias_init: Distribution,
+    // added to the weight or bias when it is mutated
+    pub weight_perturbation: Distribution,
+    pub bias_perturbation: Distribution,
+    // the factor every weight is multiplied by in the ScaleWeights mutation
+    pub weight_scale: Distribution,
+}
+
+impl Default for WeightConfig {
+    fn default() -> Self {
+        WeightConfig {
+            weight_init: Distribution::Normal {
+                mean: 0.0,
+                std_dev: 1.0,
+            },
+            bias_init: Distribution::Normal {
+                mean: 0.0,
+                std_dev: 1.0,
+            },
+            weight_perturbation: Distribution::Normal {
+                mean: 0.0,
+                std_dev: 0.5,
+            },
+            bias_perturbation: Distribution::Normal {
+                mean: 0.0,
+                std_dev: 0.5,
+            },
+            weight_scale: Distribution::Uniform {
+                low: 0.8,
+                high: 1.2,
+            },
+        }
+    }
+}
+
+// The mutations a network can go through
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum MutationOperator {
+    // perturbs one weight, or replaces it with replace_weight_probability
+    Weight,
+    Bias,
+    // gives one connection a fresh weight from weight_init
+    ReplaceWeight,
+    // multiplies all weights by the same factor
+    ScaleWeights,
+    AddNode,
+    AddConnection,
+    Squash,
+    RemoveNode,
+    // disables a connection, the gene is kept
+    RemoveConnection,
+    // enables or disables a connection
+    ToggleConnection,
+    Gate,
+    Ungate,
+}
+
+impl MutationOperator {
+    pub const ALL: [MutationOperator; 12] = [
+        MutationOperator::Weight,
+        MutationOperator::Bias,
+        MutationOperator::ReplaceWeight,
+        MutationOperator::ScaleWeights,
+        MutationOperator::AddNode,
+        MutationOperator::AddConnection,
+        MutationOperator::Squash,
+        MutationOperator::RemoveNode,
+        MutationOperator::RemoveConnection,
+        MutationOperator::ToggleConnection,
+        MutationOperator::Gate,
+        MutationOperator::Ungate,
+    ];
+}
+
+// How a network is mutated when it is made for the next generation
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MutationConfig {
+    pub weights: WeightConfig,
+    // every operator is tried once per genome, on its own, with this probability
+    pub probabilities: Vec<(MutationOperator, f32)>,
+    // the chance a weight mutation draws a fresh weight instead of perturbing it
+    pub replace_weight_probability: f32,
+    // the perturbation of a weight or bias is multiplied by this
+    pub perturbation_power: f32,
+    // structural mutations going over these are skipped, None means no limit
+    pub max_nodes: Option<usize>,
+    // counts every connection gene, the disabled ones as well
+    pub max_connections: Option<usize>,
+    // weights and biases are clamped to these ranges after every mutation, None means no limit
+    pub weight_range: Option<(f32, f32)>,
+    pub bias_range: Option<(f32, f32)>,
+    // every genome carries its own perturbation step when this is set
+    pub self_adaptation: Option<SelfAdaptation>,
+}
+
+impl MutationConfig {
+    pub fn probability(&self, operator: MutationOperator) -> f32 {
+        self.probabilities
+            .iter()
+            .find(|(op, _)| *op == operator)
+            .map_or(0.0, |(_, p)| *p)
+    }
+
+    pub fn set_probability(&mut self, operator: MutationOperator, probability: f32) {
+        match self
+            .probabilities
+            .iter_mut()
+            .find(|(op, _)| *op == operator)
+        {
+            Some((_, p)) => *p = probability,
+            None => self.probabilities.push((operator, probability)),
+        }
+    }
+}
+
+impl Default for MutationConfig {
+    fn default() -> Self {
+        MutationConfig {
+            weights: WeightConfig::default(),
+            probabilities: vec![
+                (MutationOperator::Weight, 0.8),
+                (MutationOperator::Bias, 0.5),
+                (MutationOperator::ReplaceWeight, 0.05),
+                (MutationOperator::ScaleWeights, 0.02),
+                (MutationOperator::AddNode, 0.1),
+                (MutationOperator::AddConnection, 0.2),
+                (MutationOperator::Squash, 0.1),
+                (MutationOperator::RemoveNode, 0.05),
+                (MutationOperator::RemoveConnection, 0.1),
+                (MutationOperator::ToggleConnection, 0.05),
+                (MutationOperator::Gate, 0.05),
+                (MutationOperator::Ungate, 0.05),
+            ],
+            replace_weight_probability: 0.1,
+            perturbation_power: 1.0,
+            max_nodes: None,
+            max_connections: None,
+            weight_range: Some((-10.0, 10.0)),
+            bias_range: Some((-10.0, 10.0)),
+            self_adaptation: None,
+        }
+    }
+}
+
+// Self-adaptive step sizes as in evolution strategies. The perturbations of a genome are
+// scaled by its own step, which is multiplied by exp(learning_rate * N(0, 1)) before every
+// mutation and inherited by the children, so the steps that work well survive
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct SelfAdaptation {
+    pub learning_rate: f32,
+    // the step is kept between these, so it can not vanish or explode
+    pub min_step: f32,
+    pub max_step: f32,
+}
+
+impl SelfAdaptation {
+    pub fn adapt(&self, step: f32, rng: &mut dyn RngCore) -> f32 {
+        let step = step * (self.learning_rate * distributions::normal(rng, 0.0, 1.0)).exp();
+        step.clamp(self.min_step, self.max_step)
+    }
+}
+
+impl Default for SelfAdaptation {
+    fn default() -> Self {
+        SelfAdaptation {
+            learning_rate: 0.2,
+            min_step: 0.001,
+            max_step: 10.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_mutation {
+    use super::*;
+
+    #[test]
+    fn test_probability() {
+        let mut config = MutationConfig::default();
+        assert_eq!(config.probability(MutationOperator::Weight), 0.8);
+
+        config.set_probability(MutationOperator::Weight, 0.3);
+        assert_eq!(config.probability(MutationOperator::Weight), 0.3);
+
+        config.probabilities.clear();
+        assert_eq!(config.probability(MutationOperator::Gate), 0.0);
+        config.set_probability(MutationOperator::Gate, 1.0);
+        assert_eq!(config.probabilities, vec![(MutationOperator::Gate, 1.0)]);
+    }
+
+    #[test]
+    fn test_adapt() {
+        let adaptation = SelfAdaptation::default();
+        let mut rng = rand::thread_rng();
+        let mut step = 1.0;
+        for _ in 0..1000 {
+            step = adaptation.adapt(step, &mut rng);
+            assert!(step >= adaptation.min_step && step <= adaptation.max_step);
+        }
+
+        // without a learning rate the step stays the same
+        let fixed = SelfAdaptation {
+            learning_rate: 0.0,
+            ..adaptation
+        };
+        assert_eq!(fixed.adapt(0.5, &mut rng), 0.5);
+    }
+}
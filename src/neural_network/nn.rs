@@ -1,28 +1,92 @@
-use super::super::helpers;
 use super::activation_functions::ActivationFunctions;
+use super::error::NnError;
 use super::genes::{ConnectionGene, NodeGene, NodeType};
-use rand::distributions::WeightedIndex;
+use super::innovation::InnovationTracker;
+use super::mutation::{MutationConfig, MutationOperator, WeightConfig};
+use super::species::SpeciationConfig;
 use rand::prelude::*;
-use rand::Rng;
-use std::collections::HashMap;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 
-const MUTATE_METHOD: [&str; 7] = ["weight", "bias", "node", "conn", "squash", "rnode", "rconn"];
-const MUTATE_METHOD_WEIGHTS: [u32; 7] = [1, 1, 1, 1, 1, 1, 1];
+// the chance a gene disabled in one of the parents is disabled in the child
+const DISABLED_INHERITANCE: f32 = 0.75;
 
+// The connection genes of two networks lined up by innovation number.
+// The first element of the disjoint and excess tuples belongs to the first network
 #[derive(Debug, PartialEq)]
+pub struct GeneAlignment {
+    pub matching: Vec<(ConnectionGene, ConnectionGene)>,
+    pub disjoint: (Vec<ConnectionGene>, Vec<ConnectionGene>),
+    pub excess: (Vec<ConnectionGene>, Vec<ConnectionGene>),
+}
+
+// Where the signal of a connection is read from when the network is evaluated
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    // the activation of the node in this update
+    Current(u32),
+    // the activation from the last update, the index in EvaluationOrder::previous
+    Previous(usize),
+}
+
+// A connection into a node, the weight is read from connection_genes[connection]
+// so the order stays valid when the weights change
+#[derive(Debug, Clone)]
+struct Incoming {
+    connection: usize,
+    from: Source,
+    gate: Option<Source>,
+}
+
+// The order the nodes are evaluated in, with the enabled connections into every node
+#[derive(Debug, Clone)]
+pub struct EvaluationOrder {
+    pub nodes: Vec<u32>,
+    incoming: Vec<Vec<Incoming>>,
+    // the nodes recurrent connections and gates read the last activation of
+    previous: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NN {
     pub node_genes_network: Vec<Vec<u32>>,
     pub node_genes: HashMap<u32, NodeGene>,
     pub connection_genes: Vec<ConnectionGene>,
     pub fitness: f32,
-    pub next_id: u32,
+    // the order the nodes are evaluated in, None when the structure has changed
+    #[serde(skip)]
+    pub evaluation_order: Option<EvaluationOrder>,
+    // allows connections backwards and to itself, they carry the activation from the last update
+    #[serde(default)]
+    pub recurrent: bool,
+    // scales the weight and bias perturbations when the mutation is self-adaptive,
+    // it is mutated and inherited along with the genes
+    pub mutation_step: f32,
+}
+
+// the evaluation order is a cache, two networks with the same genes are equal
+impl PartialEq for NN {
+    fn eq(&self, other: &NN) -> bool {
+        self.node_genes_network == other.node_genes_network
+            && self.node_genes == other.node_genes
+            && self.connection_genes == other.connection_genes
+            && self.fitness == other.fitness
+            && self.recurrent == other.recurrent
+            && self.mutation_step == other.mutation_step
+    }
 }
 
 impl NN {
-    pub fn new(input_layer: Vec<NodeGene>, output_layer: Vec<NodeGene>) -> Self {
+    pub fn new(
+        input_layer: Vec<NodeGene>,
+        output_layer: Vec<NodeGene>,
+        innovation: &mut InnovationTracker,
+        weights: &WeightConfig,
+        rng: &mut dyn RngCore,
+    ) -> Self {
         // Connects every input_layer node to the output_layer node
         let mut connection_genes = vec![];
-        let mut next_id = 0;
 
         for (i, _) in input_layer.clone().into_iter().enumerate() {
             if input_layer[i].node_type != NodeType::Input {
@@ -35,10 +99,10 @@ impl NN {
                 connection_genes.push(ConnectionGene::new(
                     input_layer[i].id,
                     output_layer[j].id,
-                    helpers::std0(),
+                    weights.weight_init.sample(rng),
                     -1,
+                    innovation.get_connection_innovation(input_layer[i].id, output_layer[j].id),
                 ));
-                next_id += 1;
             }
         }
 
@@ -55,109 +119,569 @@ impl NN {
         }
 
         NN {
-            node_genes_network: node_genes_network,
-            node_genes: node_genes,
-            connection_genes: connection_genes,
+            node_genes_network,
+            node_genes,
+            connection_genes,
             fitness: 0.0,
-            next_id: next_id,
+            evaluation_order: None,
+            recurrent: false,
+            mutation_step: 1.0,
         }
     }
 
-    // Tried with derive(Copy, Clone)...
-    pub fn clone(nn: &NN) -> NN {
-        let mut node_genes_network = vec![];
+    pub fn align_genes(nn1: &NN, nn2: &NN) -> GeneAlignment {
+        let mut genes1 = nn1.connection_genes.clone();
+        let mut genes2 = nn2.connection_genes.clone();
+        genes1.sort_by_key(|conn| conn.innovation);
+        genes2.sort_by_key(|conn| conn.innovation);
+
+        let mut alignment = GeneAlignment {
+            matching: vec![],
+            disjoint: (vec![], vec![]),
+            excess: (vec![], vec![]),
+        };
 
-        for layer in nn.node_genes_network.clone() {
-            let mut network_layer = vec![];
-            for node in layer {
-                network_layer.push(node.clone());
+        let (mut i, mut j) = (0, 0);
+        while i < genes1.len() && j < genes2.len() {
+            let (conn1, conn2) = (genes1[i], genes2[j]);
+            if conn1.innovation == conn2.innovation {
+                alignment.matching.push((conn1, conn2));
+                i += 1;
+                j += 1;
+            } else if conn1.innovation < conn2.innovation {
+                alignment.disjoint.0.push(conn1);
+                i += 1;
+            } else {
+                alignment.disjoint.1.push(conn2);
+                j += 1;
             }
-            node_genes_network.push(network_layer)
         }
 
+        // what is left has a higher innovation than the other network has seen
+        alignment.excess.0.extend_from_slice(&genes1[i..]);
+        alignment.excess.1.extend_from_slice(&genes2[j..]);
+
+        alignment
+    }
+
+    pub fn crossover(parent1: &NN, parent2: &NN, rng: &mut dyn RngCore) -> NN {
+        // NEAT crossover: matching genes are inherited at random from either parent,
+        // disjoint and excess genes are inherited from the fitter parent
+        let (fitter, other) = if parent1.calculate_fitness() >= parent2.calculate_fitness() {
+            (parent1, parent2)
+        } else {
+            (parent2, parent1)
+        };
+
+        let alignment = NN::align_genes(fitter, other);
+        let matching: HashMap<u32, ConnectionGene> = alignment
+            .matching
+            .iter()
+            .map(|(_, conn)| (conn.innovation, *conn))
+            .collect();
+
         let mut connection_genes = vec![];
-        for conn in nn.connection_genes.clone() {
-            connection_genes.push(conn);
+        for conn in &fitter.connection_genes {
+            let mut child_conn = match matching.get(&conn.innovation) {
+                Some(other_conn) if rng.gen::<bool>() => *other_conn,
+                _ => *conn,
+            };
+            // a gene disabled in either parent is likely to stay disabled, as in NEAT
+            if let Some(other_conn) = matching.get(&conn.innovation) {
+                if !conn.enabled || !other_conn.enabled {
+                    child_conn.enabled = rng.gen::<f32>() >= DISABLED_INHERITANCE;
+                }
+            }
+            connection_genes.push(child_conn);
         }
 
-        NN {
-            node_genes_network: node_genes_network,
-            node_genes: nn.node_genes.clone(),
-            connection_genes: connection_genes,
-            fitness: nn.fitness,
-            next_id: nn.next_id,
+        // the structure comes from the fitter parent, shared nodes inherit at random
+        // the nodes are visited by id, so the same random numbers give the same child
+        let mut node_genes = fitter.node_genes.clone();
+        let mut ids: Vec<u32> = node_genes.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let node = node_genes.get_mut(&id).unwrap();
+            if let Some(other_node) = other.node_genes.get(&id) {
+                if rng.gen::<bool>() {
+                    node.bias = other_node.bias;
+                    node.squash = other_node.squash;
+                }
+            }
+        }
+
+        let mut child = NN {
+            node_genes_network: fitter.node_genes_network.clone(),
+            node_genes,
+            connection_genes,
+            fitness: 0.0,
+            evaluation_order: None,
+            recurrent: fitter.recurrent,
+            // intermediate recombination, the geometric mean of the two steps
+            mutation_step: (fitter.mutation_step * other.mutation_step).sqrt(),
+        };
+
+        // a gate from the other parent can point to a node this network does not have
+        let layers = child.get_layer_indices();
+        for i in 0..child.connection_genes.len() {
+            let conn = child.connection_genes[i];
+            if let Some(gater) = NN::get_gater(&conn) {
+                if !child.can_gate(gater, &conn, &layers) {
+                    child.connection_genes[i].gater = -1;
+                }
+            }
         }
+        child
     }
 
-    fn get_output(&mut self) -> usize {
-        // return the index of the output node with highest value
-        for conn in &self.connection_genes {
-            let from_value = match self.node_genes.get_mut(&conn.from) {
-                Some(node) => node.get_output(),
+    pub fn compatibility_distance(nn1: &NN, nn2: &NN, config: &SpeciationConfig) -> f32 {
+        let alignment = NN::align_genes(nn1, nn2);
+        let excess = (alignment.excess.0.len() + alignment.excess.1.len()) as f32;
+        let disjoint = (alignment.disjoint.0.len() + alignment.disjoint.1.len()) as f32;
+
+        let mut weight_difference = 0.0;
+        if !alignment.matching.is_empty() {
+            for (conn1, conn2) in &alignment.matching {
+                weight_difference += (conn1.weight - conn2.weight).abs();
+            }
+            weight_difference /= alignment.matching.len() as f32;
+        }
+
+        // small genomes are not normalized, as in the NEAT paper
+        let mut genes = nn1.connection_genes.len().max(nn2.connection_genes.len()) as f32;
+        if genes < 20.0 {
+            genes = 1.0;
+        }
+
+        config.c1 * excess / genes + config.c2 * disjoint / genes + config.c3 * weight_difference
+    }
+
+    pub fn invalidate_evaluation_order(&mut self) {
+        // has to be called when the connections or nodes change
+        self.evaluation_order = None;
+    }
+
+    fn get_layer_indices(&self) -> HashMap<u32, usize> {
+        let mut layers = HashMap::new();
+        for (i, layer) in self.node_genes_network.iter().enumerate() {
+            for id in layer {
+                layers.insert(*id, i);
+            }
+        }
+        layers
+    }
+
+    fn is_recurrent_connection(&self, conn: &ConnectionGene, layers: &HashMap<u32, usize>) -> bool {
+        // a connection is recurrent when it goes to the same or an earlier layer
+        if !self.recurrent {
+            return false;
+        }
+        match (layers.get(&conn.from), layers.get(&conn.to)) {
+            (Some(from), Some(to)) => from >= to,
+            _ => false,
+        }
+    }
+
+    fn get_gater(conn: &ConnectionGene) -> Option<u32> {
+        if conn.gater < 0 {
+            None
+        } else {
+            Some(conn.gater as u32)
+        }
+    }
+
+    fn is_recurrent_gate(&self, conn: &ConnectionGene, layers: &HashMap<u32, usize>) -> bool {
+        // like a recurrent connection, a gater in the same or a later layer gates
+        // with its activation from the last update
+        let gater = match NN::get_gater(conn) {
+            Some(gater) => gater,
+            None => return false,
+        };
+        if !self.recurrent {
+            return false;
+        }
+        match (layers.get(&gater), layers.get(&conn.to)) {
+            (Some(gater), Some(to)) => gater >= to,
+            _ => false,
+        }
+    }
+
+    fn can_gate(&self, gater: u32, conn: &ConnectionGene, layers: &HashMap<u32, usize>) -> bool {
+        // without recurrent connections the gater has to be evaluated before the
+        // node the connection goes to
+        match (layers.get(&gater), layers.get(&conn.to)) {
+            (Some(gater), Some(to)) => self.recurrent || gater < to,
+            _ => false,
+        }
+    }
+
+    fn get_evaluation_order(&mut self) -> Vec<u32> {
+        if self.evaluation_order.is_none() {
+            self.evaluation_order = Some(self.compute_evaluation_order());
+        }
+        self.evaluation_order.as_ref().unwrap().nodes.clone()
+    }
+
+    fn compute_evaluation_order(&self) -> EvaluationOrder {
+        // topological sort (Kahn's algorithm), a node comes after every node it gets input from
+        // the layers decide the order between nodes that do not depend on each other
+        let mut nodes: Vec<u32> = self.node_genes_network.concat();
+        let mut other_nodes: Vec<u32> = self
+            .node_genes
+            .keys()
+            .filter(|id| !nodes.contains(id))
+            .cloned()
+            .collect();
+        other_nodes.sort();
+        nodes.extend(other_nodes);
+
+        let layers = self.get_layer_indices();
+        let mut in_degree: HashMap<u32, usize> = nodes.iter().map(|id| (*id, 0)).collect();
+        let mut outgoing: HashMap<u32, Vec<u32>> = HashMap::new();
+        for conn in self.connection_genes.iter().filter(|conn| conn.enabled) {
+            if !self.node_genes.contains_key(&conn.from) || !self.node_genes.contains_key(&conn.to)
+            {
+                continue;
+            }
+            // recurrent connections use the last activation, so they do not decide the order
+            if self.is_recurrent_connection(conn, &layers) {
+                continue;
+            }
+            *in_degree.get_mut(&conn.to).unwrap() += 1;
+            outgoing.entry(conn.from).or_default().push(conn.to);
+        }
+
+        // a gated connection also needs the activation of the gater
+        for conn in self.connection_genes.iter().filter(|conn| conn.enabled) {
+            let gater = match NN::get_gater(conn) {
+                Some(gater) => gater,
                 None => continue,
             };
+            if !self.node_genes.contains_key(&gater)
+                || !self.node_genes.contains_key(&conn.to)
+                || self.is_recurrent_gate(conn, &layers)
+            {
+                continue;
+            }
+            *in_degree.get_mut(&conn.to).unwrap() += 1;
+            outgoing.entry(gater).or_default().push(conn.to);
+        }
 
-            match self.node_genes.get_mut(&conn.to) {
-                Some(node) => node.add_value(from_value),
-                None => continue,
+        let mut queue: VecDeque<u32> = nodes
+            .iter()
+            .filter(|id| in_degree[id] == 0)
+            .cloned()
+            .collect();
+        let mut order = vec![];
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+            if let Some(targets) = outgoing.get(&id) {
+                for to in targets {
+                    let degree = in_degree.get_mut(to).unwrap();
+                    *degree -= 1;
+                    if *degree == 0 {
+                        queue.push_back(*to);
+                    }
+                }
             }
         }
 
-        let mut cur: (f32, usize) = (0.0, 0);
+        // nodes in a cycle never get all their inputs and are left out
+        // the connections into every node are looked up once, instead of every update
+        let position: HashMap<u32, usize> =
+            order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut incoming = vec![vec![]; order.len()];
+        let mut previous = vec![];
+        let mut previous_source = |id: u32| {
+            let i = previous.iter().position(|p| *p == id).unwrap_or_else(|| {
+                previous.push(id);
+                previous.len() - 1
+            });
+            Source::Previous(i)
+        };
+        for (i, conn) in self.connection_genes.iter().enumerate() {
+            let to = match position.get(&conn.to) {
+                Some(to) if conn.enabled => *to,
+                _ => continue,
+            };
+            let from = if self.is_recurrent_connection(conn, &layers) {
+                previous_source(conn.from)
+            } else {
+                Source::Current(conn.from)
+            };
+            let gate = NN::get_gater(conn).map(|gater| {
+                if self.is_recurrent_gate(conn, &layers) {
+                    previous_source(gater)
+                } else {
+                    Source::Current(gater)
+                }
+            });
+            incoming[to].push(Incoming {
+                connection: i,
+                from,
+                gate,
+            });
+        }
+
+        EvaluationOrder {
+            nodes: order,
+            incoming,
+            previous,
+        }
+    }
+
+    fn evaluate(&mut self) {
+        // every node is evaluated once, after all its inputs are summed
+        self.get_evaluation_order();
+        let order = self.evaluation_order.take().unwrap();
+
+        // the activations from the last update, used by recurrent connections and gates
+        let previous: Vec<Option<f32>> = order
+            .previous
+            .iter()
+            .map(|id| self.node_genes.get(id).map(|node| node.activation))
+            .collect();
+        let activation = |nn: &NN, source: Source| match source {
+            Source::Current(id) => nn.node_genes.get(&id).map(|node| node.activation),
+            Source::Previous(i) => previous[i],
+        };
 
+        for (id, incoming) in order.nodes.iter().zip(&order.incoming) {
+            let mut value = 0.0;
+            for link in incoming {
+                let mut signal = match activation(self, link.from) {
+                    Some(from) => from * self.connection_genes[link.connection].weight,
+                    None => continue,
+                };
+
+                // a gated connection is multiplied by the activation of the gater
+                if let Some(gate) = link.gate {
+                    signal *= activation(self, gate).unwrap_or(0.0);
+                }
+                value += signal;
+            }
+
+            if let Some(node) = self.node_genes.get_mut(id) {
+                node.add_value(value);
+                node.activation = node.get_output();
+            }
+        }
+        self.evaluation_order = Some(order);
+    }
+
+    fn get_output_values(&self) -> Vec<f32> {
         let outputs = match self.node_genes_network.last() {
             Some(node) => node,
             e => panic!("{:?}", e),
         };
 
-        for (i, node_id) in outputs.iter().enumerate() {
-            match self.node_genes.get_mut(&node_id) {
-                Some(node) => {
-                    let value = node.get_output();
-                    // set cur if it is the first or get the requirements
-                    if value > cur.0 || i == 0 {
-                        cur.0 = value;
-                        cur.1 = i;
-                    }
-                }
+        let mut values = vec![];
+        for node_id in outputs {
+            match self.node_genes.get(node_id) {
+                Some(node) => values.push(node.activation),
                 _ => println!("This should give a value..."),
             };
         }
-        cur.1
+        values
+    }
+
+    pub fn get_output(&mut self) -> usize {
+        // return the index of the output node with highest value
+        self.evaluate();
+        NN::argmax(&self.get_output_values())
     }
 
     fn reset_gene_value(&mut self) {
         // want to reset the value (set it to 0) when you have got an ouput
         for row in &self.node_genes_network {
             for key in row {
-                match self.node_genes.get_mut(key) {
-                    Some(node) => node.value = 0.0,
-                    _ => (),
+                if let Some(node) = self.node_genes.get_mut(key) {
+                    node.value = 0.0;
                 }
             }
         }
     }
 
-    pub fn update(&mut self, input_values: Vec<f32>) -> usize {
+    pub fn reset_state(&mut self) {
+        // forgets the activations from earlier updates, e.g. when a new episode starts
+        for node in self.node_genes.values_mut() {
+            node.value = 0.0;
+            node.activation = 0.0;
+        }
+    }
+
+    pub fn activate(&mut self, input_values: &[f32]) -> Vec<f32> {
+        // returns the activation of every output node
         for (i, key) in self.node_genes_network[0].iter().enumerate() {
-            match self.node_genes.get_mut(&key) {
+            match self.node_genes.get_mut(key) {
                 Some(node) => node.add_value(input_values[i]),
                 _ => continue,
             }
         }
-        let res = self.get_output();
+        self.evaluate();
+        let res = self.get_output_values();
 
         self.reset_gene_value();
         res
     }
 
+    pub fn update(&mut self, input_values: Vec<f32>) -> usize {
+        self.activate_argmax(&input_values)
+    }
+
+    fn check_structure(&self) -> Result<(), NnError> {
+        // the problems that would make activate or mutate panic
+        if self.node_genes_network.len() < 2
+            || self.node_genes_network.iter().any(|layer| layer.is_empty())
+        {
+            return Err(NnError::EmptyNetwork);
+        }
+        for id in self.node_genes_network.iter().flatten() {
+            if !self.node_genes.contains_key(id) {
+                return Err(NnError::MissingNode(*id));
+            }
+        }
+        for conn in &self.connection_genes {
+            if !self.node_genes.contains_key(&conn.from) || !self.node_genes.contains_key(&conn.to)
+            {
+                return Err(NnError::DanglingConnection {
+                    from: conn.from,
+                    to: conn.to,
+                });
+            }
+            if let Some(gater) = NN::get_gater(conn) {
+                if !self.node_genes.contains_key(&gater) {
+                    return Err(NnError::MissingNode(gater));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), NnError> {
+        // checks every invariant a network should keep through mutations and crossover
+        self.check_structure()?;
+
+        let mut placed: HashMap<u32, usize> = HashMap::new();
+        for id in self.node_genes_network.iter().flatten() {
+            *placed.entry(*id).or_default() += 1;
+        }
+        let mut ids: Vec<&u32> = self.node_genes.keys().collect();
+        ids.sort();
+        for id in ids {
+            if placed.get(id) != Some(&1) {
+                return Err(NnError::MisplacedNode(*id));
+            }
+        }
+
+        let mut edges = HashSet::new();
+        for conn in &self.connection_genes {
+            if !edges.insert((conn.from, conn.to)) {
+                return Err(NnError::DuplicateConnection {
+                    from: conn.from,
+                    to: conn.to,
+                });
+            }
+            if self.node_genes[&conn.to].node_type == NodeType::Input {
+                return Err(NnError::InputHasIncoming(conn.to));
+            }
+            // a recurrent network can feed the outputs back
+            if !self.recurrent && self.node_genes[&conn.from].node_type == NodeType::Output {
+                return Err(NnError::OutputHasOutgoing(conn.from));
+            }
+        }
+
+        // disabled connections count, they can be enabled again by a mutation or crossover
+        // the copy gets its own order, the one cached for this network skips them
+        let mut all_enabled = NN::clone(self);
+        all_enabled
+            .connection_genes
+            .iter_mut()
+            .for_each(|conn| conn.enabled = true);
+        all_enabled.invalidate_evaluation_order();
+        if !self.recurrent && all_enabled.get_evaluation_order().len() < self.node_genes.len() {
+            return Err(NnError::CycleDetected);
+        }
+        Ok(())
+    }
+
+    pub fn try_activate(&mut self, input_values: &[f32]) -> Result<Vec<f32>, NnError> {
+        self.check_structure()?;
+        let expected = self.node_genes_network[0].len();
+        if input_values.len() != expected {
+            return Err(NnError::InputSizeMismatch {
+                expected,
+                got: input_values.len(),
+            });
+        }
+        // a node left out of the evaluation order is part of a cycle
+        if self.get_evaluation_order().len() < self.node_genes.len() {
+            return Err(NnError::CycleDetected);
+        }
+        Ok(self.activate(input_values))
+    }
+
+    pub fn try_update(&mut self, input_values: Vec<f32>) -> Result<usize, NnError> {
+        Ok(NN::argmax(&self.try_activate(&input_values)?))
+    }
+
+    pub fn activate_argmax(&mut self, input_values: &[f32]) -> usize {
+        NN::argmax(&self.activate(input_values))
+    }
+
+    pub fn activate_softmax(&mut self, input_values: &[f32], rng: &mut dyn RngCore) -> usize {
+        // picks an output at random, the higher the activation the more likely
+        let probabilities = NN::softmax(&self.activate(input_values));
+        let mut point = rng.gen::<f32>();
+        for (i, probability) in probabilities.iter().enumerate() {
+            if point < *probability {
+                return i;
+            }
+            point -= probability;
+        }
+        probabilities.len().saturating_sub(1)
+    }
+
+    pub fn activate_multi_label(&mut self, input_values: &[f32], threshold: f32) -> Vec<bool> {
+        // every output above the threshold is on
+        self.activate(input_values)
+            .iter()
+            .map(|value| *value > threshold)
+            .collect()
+    }
+
+    pub fn argmax(values: &[f32]) -> usize {
+        // the first one wins if more than one has the highest value
+        let mut cur: (f32, usize) = (0.0, 0);
+        for (i, value) in values.iter().enumerate() {
+            if *value > cur.0 || i == 0 {
+                cur.0 = *value;
+                cur.1 = i;
+            }
+        }
+        cur.1
+    }
+
+    pub fn softmax(values: &[f32]) -> Vec<f32> {
+        // the highest value is subtracted so exp can not overflow
+        let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let exp: Vec<f32> = values.iter().map(|value| (value - max).exp()).collect();
+        let sum: f32 = exp.iter().sum();
+        exp.iter().map(|value| value / sum).collect()
+    }
+
     pub fn calculate_fitness(&self) -> f32 {
         let mut fitenss = self.fitness;
         self.node_genes_network
             .iter()
             .for_each(|f| fitenss -= f.len() as f32);
 
-        fitenss -= self.connection_genes.len() as f32;
+        // only the connections that are used cost fitness
+        fitenss -= self
+            .connection_genes
+            .iter()
+            .filter(|conn| conn.enabled)
+            .count() as f32;
         fitenss
     }
 
@@ -166,42 +690,123 @@ impl NN {
         self.fitness += reward;
     }
 
-    fn get_next_id(&mut self) -> u32 {
-        self.next_id += 1;
-        self.next_id
-    }
-
-    pub fn mutate(&mut self) {
-        // This adds a random mutation (e.g. extra node, or adjusted weight)
-        let mut rng = thread_rng();
-        let dist = WeightedIndex::new(&MUTATE_METHOD_WEIGHTS).unwrap();
-        let res = MUTATE_METHOD[dist.sample(&mut rng)];
-        match res {
-            "weight" => self.mutate_weight(),
-            "bias" => self.mutate_bias(),
-            "node" => self.mutate_new_node(),
-            "conn" => self.mutate_new_connection(),
-            "squash" => self.mutate_squash(),
-            "rnode" => self.mutate_remove_node(),
-            "rconn" => self.mutate_remove_connection(),
-            _ => (),
+    pub fn mutate(
+        &mut self,
+        innovation: &mut InnovationTracker,
+        config: &MutationConfig,
+        rng: &mut dyn RngCore,
+    ) {
+        if let Some(adaptation) = &config.self_adaptation {
+            self.mutation_step = adaptation.adapt(self.mutation_step, rng);
+        }
+
+        // Every operator gets its own chance, so a network can get several
+        // mutations (e.g. extra node and adjusted weight) or none at all
+        // an operator missing from the config is never applied
+        for operator in MutationOperator::ALL {
+            if rng.gen::<f32>() < config.probability(operator) {
+                self.apply_mutation(operator, innovation, config, rng);
+
+                // catches a mutation that breaks the network where it happens
+                debug_assert_eq!(self.validate(), Ok(()), "after the {:?} mutation", operator);
+            }
+        }
+    }
+
+    pub fn apply_mutation(
+        &mut self,
+        operator: MutationOperator,
+        innovation: &mut InnovationTracker,
+        config: &MutationConfig,
+        rng: &mut dyn RngCore,
+    ) {
+        let nodes = self.node_genes.len();
+        // the limit counts every connection gene, the disabled ones as well
+        let connections = self.connection_genes.len();
+        match operator {
+            MutationOperator::Weight => self.mutate_weight(config, rng),
+            MutationOperator::Bias => self.mutate_bias(config, rng),
+            MutationOperator::ReplaceWeight => self.mutate_replace_weight(&config.weights, rng),
+            MutationOperator::ScaleWeights => self.mutate_scale_weights(&config.weights, rng),
+            MutationOperator::AddNode => {
+                // a new node always comes with two new connection genes
+                if config.max_nodes.is_none_or(|max| nodes < max)
+                    && config
+                        .max_connections
+                        .is_none_or(|max| connections + 2 <= max)
+                {
+                    self.mutate_new_node(innovation, &config.weights, rng)
+                }
+            }
+            MutationOperator::AddConnection => {
+                // at most one new connection gene, a disabled one can be enabled instead
+                if config.max_connections.is_none_or(|max| connections < max) {
+                    self.mutate_new_connection(innovation, &config.weights, rng)
+                }
+            }
+            MutationOperator::Squash => self.mutate_squash(rng),
+            MutationOperator::RemoveNode => self.mutate_remove_node(rng),
+            MutationOperator::RemoveConnection => self.mutate_remove_connection(rng),
+            MutationOperator::ToggleConnection => self.mutate_toggle_connection(rng),
+            MutationOperator::Gate => self.mutate_gate(rng),
+            MutationOperator::Ungate => self.mutate_ungate(rng),
         }
+        self.clamp_genes(config);
     }
 
-    fn mutate_weight(&mut self) {
-        // This method takes a random connection and changes its weight
-        match self.connection_genes.choose_mut(&mut rand::thread_rng()) {
-            Some(conn) => conn.weight += helpers::lib::std0(),
+    fn clamp_genes(&mut self, config: &MutationConfig) {
+        // repeated mutations would otherwise let the weights and biases grow without bound
+        if let Some((min, max)) = config.weight_range {
+            for conn in self.connection_genes.iter_mut() {
+                conn.weight = conn.weight.clamp(min, max);
+            }
+        }
+        if let Some((min, max)) = config.bias_range {
+            for node in self.node_genes.values_mut() {
+                node.bias = node.bias.clamp(min, max);
+            }
+        }
+    }
+
+    pub fn try_mutate(
+        &mut self,
+        innovation: &mut InnovationTracker,
+        config: &MutationConfig,
+        rng: &mut dyn RngCore,
+    ) -> Result<(), NnError> {
+        // the network is left as it is if it is broken, mutate expects a valid network
+        self.validate()?;
+        self.mutate(innovation, config, rng);
+        Ok(())
+    }
+
+    fn perturbation_scale(&self, config: &MutationConfig) -> f32 {
+        // the genome's own step is only used in the self-adaptive mode
+        match config.self_adaptation {
+            Some(_) => config.perturbation_power * self.mutation_step,
+            None => config.perturbation_power,
+        }
+    }
+
+    fn mutate_weight(&mut self, config: &MutationConfig, rng: &mut dyn RngCore) {
+        // This method takes a random connection and either perturbs its weight
+        // or gives it a new one
+        let replace = rng.gen::<f32>() < config.replace_weight_probability;
+        let scale = self.perturbation_scale(config);
+        match self.connection_genes.choose_mut(rng) {
+            Some(conn) if replace => conn.weight = config.weights.weight_init.sample(rng),
+            Some(conn) => conn.weight += config.weights.weight_perturbation.sample(rng) * scale,
             _ => (),
         }
     }
 
-    fn mutate_bias(&mut self) {
+    fn mutate_bias(&mut self, config: &MutationConfig, rng: &mut dyn RngCore) {
         // This method takes a random node and changes its bias
-        match self.node_genes_network.choose_mut(&mut rand::thread_rng()) {
-            Some(layer) => match layer.choose_mut(&mut rand::thread_rng()) {
+        let scale = self.perturbation_scale(config);
+        match self.node_genes_network.choose_mut(rng) {
+            Some(layer) => match layer.choose_mut(rng) {
                 Some(key) => match self.node_genes.get_mut(key) {
-                    Some(node) => node.bias += helpers::lib::std0(),
+                    Some(node) => node.bias += config.weights.bias_perturbation.sample(rng) * scale,
                     e => panic!("{:?}", e),
                 },
                 e => panic!("{:?}", e),
@@ -210,88 +815,116 @@ impl NN {
         };
     }
 
-    fn mutate_new_node(&mut self) {
+    fn mutate_replace_weight(&mut self, weights: &WeightConfig, rng: &mut dyn RngCore) {
+        // This method gives a random connection a new weight, unrelated to the old one
+        if let Some(conn) = self.connection_genes.choose_mut(rng) {
+            conn.weight = weights.weight_init.sample(rng);
+        }
+    }
+
+    fn mutate_scale_weights(&mut self, weights: &WeightConfig, rng: &mut dyn RngCore) {
+        // This method changes the magnitude of all weights, keeping their ratios
+        let factor = weights.weight_scale.sample(rng);
+        for conn in self.connection_genes.iter_mut() {
+            conn.weight *= factor;
+        }
+    }
+
+    fn mutate_new_node(
+        &mut self,
+        innovation: &mut InnovationTracker,
+        weights: &WeightConfig,
+        rng: &mut dyn RngCore,
+    ) {
         // When you add a extra node, it reduces the fitness
         self.fitness -= 1.0;
-        let mut rng = rand::thread_rng();
         let upper_limit = self.node_genes_network.len() - 2;
         let layer_no = rng.gen_range(0..=upper_limit);
 
-        let from_layer;
-        let to_layer;
+        // layer 0 means a new hidden layer in front of the output layer
+        let (from_layer, to_layer, new_layer) = if layer_no == 0 {
+            (upper_limit, upper_limit + 1, upper_limit + 1)
+        } else {
+            (layer_no - 1, layer_no + 1, layer_no)
+        };
+
+        // an enabled connection between the two layers is split and disabled, as in NEAT,
+        // otherwise the node goes between two random nodes of the layers
+        let split: Vec<usize> = (0..self.connection_genes.len())
+            .filter(|i| {
+                let conn = &self.connection_genes[*i];
+                conn.enabled
+                    && self.node_genes_network[from_layer].contains(&conn.from)
+                    && self.node_genes_network[to_layer].contains(&conn.to)
+            })
+            .collect();
+        let (from_node_id, to_node_id, split_weight) = match split.choose(rng) {
+            Some(i) => {
+                let conn = &mut self.connection_genes[*i];
+                conn.enabled = false;
+                (conn.from, conn.to, Some(conn.weight))
+            }
+            None => (
+                *self.node_genes_network[from_layer].choose(rng).unwrap(),
+                *self.node_genes_network[to_layer].choose(rng).unwrap(),
+                None,
+            ),
+        };
 
-        let id = self.get_next_id();
+        // networks putting a node between the same two nodes share the node id
+        let mut id = innovation.get_split_node_id(from_node_id, to_node_id);
+        if self.node_genes.contains_key(&id) {
+            id = innovation.new_node_id();
+        }
         let node = NodeGene::new(
             id,
             NodeType::Hidden,
-            helpers::lib::std0(),
-            ActivationFunctions::get_random(),
+            weights.bias_init.sample(rng),
+            ActivationFunctions::get_random(rng),
             0.0,
         );
         self.node_genes.insert(id, node);
 
-        let mut check_before = vec![];
-
         if layer_no == 0 {
-            from_layer = upper_limit;
-            to_layer = upper_limit + 1;
-
-            let new_layer = vec![id];
-            self.node_genes_network.insert(upper_limit, new_layer);
-
-            for (i, layer) in self.node_genes_network.iter().enumerate() {
-                if i == upper_limit {
-                    break;
-                }
-                for row in layer {
-                    check_before.push(row);
-                }
-            }
+            self.node_genes_network.insert(new_layer, vec![id]);
         } else {
-            from_layer = layer_no - 1;
-            to_layer = layer_no + 1;
-            self.node_genes_network[layer_no].push(id);
-            for (i, layer) in self.node_genes_network.iter().enumerate() {
-                if i == layer_no {
-                    break;
-                }
-                for row in layer {
-                    check_before.push(row);
-                }
-            }
-        }
-
-        let mut rng = rand::thread_rng();
-        let from_node_id = self.node_genes_network[from_layer]
-            .choose(&mut rng)
-            .unwrap();
-        let to_node_id = self.node_genes_network[to_layer].choose(&mut rng).unwrap();
-        let conn1 = ConnectionGene::new(*from_node_id, id, helpers::lib::std0(), -1);
-        let conn2 = ConnectionGene::new(id, *to_node_id, helpers::lib::std0(), -1);
-
-        let mut inserted_conn1 = false;
-
-        for i in 0..self.connection_genes.len() {
-            if self.connection_genes[i].from == *from_node_id {
-                self.connection_genes.insert(i, conn1);
-                inserted_conn1 = true;
-                break;
-            }
+            self.node_genes_network[new_layer].push(id);
         }
 
-        if !inserted_conn1 {
-            self.connection_genes.push(conn1);
-        }
+        // a split connection keeps its weight after the new node, and gets 1.0 in front of it
+        let (weight1, weight2) = match split_weight {
+            Some(weight) => (1.0, weight),
+            None => (
+                weights.weight_init.sample(rng),
+                weights.weight_init.sample(rng),
+            ),
+        };
+        let conn1 = ConnectionGene::new(
+            from_node_id,
+            id,
+            weight1,
+            -1,
+            innovation.get_connection_innovation(from_node_id, id),
+        );
+        let conn2 = ConnectionGene::new(
+            id,
+            to_node_id,
+            weight2,
+            -1,
+            innovation.get_connection_innovation(id, to_node_id),
+        );
 
-        for i in 0..self.connection_genes.len() {
-            if !check_before.contains(&&id) {
-                self.connection_genes.insert(i, conn2);
-                break;
-            }
-        }
+        self.connection_genes.push(conn1);
+        self.connection_genes.push(conn2);
+        self.invalidate_evaluation_order();
     }
 
-    fn mutate_new_connection(&mut self) {
+    fn mutate_new_connection(
+        &mut self,
+        innovation: &mut InnovationTracker,
+        weights: &WeightConfig,
+        rng: &mut dyn RngCore,
+    ) {
         // if all connections are made
         let mut count = 0;
         loop {
@@ -301,17 +934,33 @@ impl NN {
                 break;
             }
             let mut made_new_conn = true;
-            let mut rng = rand::thread_rng();
-            let from_idx = rng.gen_range(0..self.node_genes_network.len() - 1);
-            let to_idx = rng.gen_range(from_idx + 1..self.node_genes_network.len());
-
-            let from_id = self.node_genes_network[from_idx].choose(&mut rng).unwrap();
-            let to_id = self.node_genes_network[to_idx].choose(&mut rng).unwrap();
+            let (from_idx, to_idx) = if self.recurrent {
+                // any node except the inputs can get a connection, also from itself
+                (
+                    rng.gen_range(0..self.node_genes_network.len()),
+                    rng.gen_range(1..self.node_genes_network.len()),
+                )
+            } else {
+                let from_idx = rng.gen_range(0..self.node_genes_network.len() - 1);
+                (
+                    from_idx,
+                    rng.gen_range(from_idx + 1..self.node_genes_network.len()),
+                )
+            };
 
-            // check if already exists
-            for conn in &self.connection_genes {
-                if conn.from == *from_id && conn.to == *to_id {
-                    made_new_conn = false;
+            let from_id = self.node_genes_network[from_idx].choose(rng).unwrap();
+            let to_id = self.node_genes_network[to_idx].choose(rng).unwrap();
+
+            // check if already exists, a disabled connection is enabled again
+            let existing = self
+                .connection_genes
+                .iter()
+                .position(|conn| conn.from == *from_id && conn.to == *to_id);
+            if let Some(i) = existing {
+                made_new_conn = false;
+                if !self.connection_genes[i].enabled {
+                    self.connection_genes[i].enabled = true;
+                    self.invalidate_evaluation_order();
                     break;
                 }
             }
@@ -321,29 +970,24 @@ impl NN {
                 continue;
             }
 
-            let mut inserted = false;
-
-            let conn = ConnectionGene::new(*from_id, *to_id, helpers::lib::std0(), -1);
-            for i in 0..self.connection_genes.len() {
-                if self.connection_genes[i].from == *from_id {
-                    self.connection_genes.insert(i, conn);
-                    inserted = true;
-                    break;
-                }
-            }
-
-            if !inserted {
-                self.connection_genes.push(conn);
-            }
+            let conn = ConnectionGene::new(
+                *from_id,
+                *to_id,
+                weights.weight_init.sample(rng),
+                -1,
+                innovation.get_connection_innovation(*from_id, *to_id),
+            );
+            self.connection_genes.push(conn);
+            self.invalidate_evaluation_order();
             break;
         }
     }
 
-    fn mutate_squash(&mut self) {
-        match self.node_genes_network.choose_mut(&mut rand::thread_rng()) {
-            Some(layer) => match layer.choose_mut(&mut rand::thread_rng()) {
+    fn mutate_squash(&mut self, rng: &mut dyn RngCore) {
+        match self.node_genes_network.choose_mut(rng) {
+            Some(layer) => match layer.choose_mut(rng) {
                 Some(key) => match self.node_genes.get_mut(key) {
-                    Some(node) => node.squash = ActivationFunctions::get_random(),
+                    Some(node) => node.squash = ActivationFunctions::get_random(rng),
                     e => panic!("{:?}", e),
                 },
                 e => panic!("{:?}", e),
@@ -352,8 +996,7 @@ impl NN {
         };
     }
 
-    fn mutate_remove_node(&mut self) {
-        let mut rng = rand::thread_rng();
+    fn mutate_remove_node(&mut self, rng: &mut dyn RngCore) {
         if self.node_genes_network.len() == 2 {
             // can only remove hidden layers
             return;
@@ -379,22 +1022,76 @@ impl NN {
             }
         });
 
+        // the connections it gated are not gated anymore
+        for conn in new_connection_genes.iter_mut() {
+            if conn.gater == node_id as i32 {
+                conn.gater = -1;
+            }
+        }
+
         self.connection_genes = new_connection_genes;
+        self.invalidate_evaluation_order();
     }
 
-    fn mutate_remove_connection(&mut self) {
-        if self.connection_genes.len() == 0 {
-            return;
+    fn mutate_remove_connection(&mut self, rng: &mut dyn RngCore) {
+        // the connection is disabled, so its history is kept for crossover
+        let enabled: Vec<usize> = (0..self.connection_genes.len())
+            .filter(|i| self.connection_genes[*i].enabled)
+            .collect();
+        if let Some(i) = enabled.choose(rng) {
+            self.connection_genes[*i].enabled = false;
+            self.invalidate_evaluation_order();
+        }
+    }
+
+    fn mutate_toggle_connection(&mut self, rng: &mut dyn RngCore) {
+        if let Some(conn) = self.connection_genes.choose_mut(rng) {
+            conn.enabled = !conn.enabled;
+            self.invalidate_evaluation_order();
+        }
+    }
+
+    fn mutate_gate(&mut self, rng: &mut dyn RngCore) {
+        // a node starts to gate a connection that is not gated
+        let layers = self.get_layer_indices();
+        let ungated: Vec<usize> = (0..self.connection_genes.len())
+            .filter(|i| self.connection_genes[*i].gater < 0)
+            .collect();
+        let i = match ungated.choose(rng) {
+            Some(i) => *i,
+            None => return,
+        };
+
+        let conn = self.connection_genes[i];
+        let gaters: Vec<u32> = self
+            .node_genes_network
+            .concat()
+            .into_iter()
+            .filter(|id| self.can_gate(*id, &conn, &layers))
+            .collect();
+        if let Some(gater) = gaters.choose(rng) {
+            self.connection_genes[i].gater = *gater as i32;
+            self.invalidate_evaluation_order();
+        }
+    }
+
+    fn mutate_ungate(&mut self, rng: &mut dyn RngCore) {
+        let gated: Vec<usize> = (0..self.connection_genes.len())
+            .filter(|i| self.connection_genes[*i].gater >= 0)
+            .collect();
+        if let Some(i) = gated.choose(rng) {
+            self.connection_genes[*i].gater = -1;
+            self.invalidate_evaluation_order();
         }
-        let mut rng = rand::thread_rng();
-        self.connection_genes
-            .remove(rng.gen_range(0..self.connection_genes.len()));
     }
 }
 
 #[cfg(test)]
 mod test_neural_network_mutations {
+    use super::super::super::helpers::distributions::Distribution;
+    use super::super::mutation::SelfAdaptation;
     use super::*;
+    use rand_chacha::ChaCha8Rng;
     #[test]
     fn test_mutate_weight() {
         let node_genes_network = vec![];
@@ -405,17 +1102,21 @@ mod test_neural_network_mutations {
             to: 0,
             weight: 0.0,
             gater: -1,
+            innovation: 0,
+            enabled: true,
         }];
 
         let mut nn = NN {
-            node_genes_network: node_genes_network,
-            node_genes: node_genes,
-            connection_genes: connection_genes,
+            node_genes_network,
+            node_genes,
+            connection_genes,
             fitness: 0.0,
-            next_id: 0,
+            evaluation_order: None,
+            recurrent: false,
+            mutation_step: 1.0,
         };
 
-        nn.mutate_weight();
+        nn.mutate_weight(&MutationConfig::default(), &mut rand::thread_rng());
 
         assert_ne!(nn.connection_genes[0].weight, 0.0);
     }
@@ -428,14 +1129,16 @@ mod test_neural_network_mutations {
         node_genes.insert(0, node);
 
         let mut nn = NN {
-            node_genes_network: node_genes_network,
-            node_genes: node_genes,
+            node_genes_network,
+            node_genes,
             connection_genes: vec![],
             fitness: 0.0,
-            next_id: 0,
+            evaluation_order: None,
+            recurrent: false,
+            mutation_step: 1.0,
         };
 
-        nn.mutate_bias();
+        nn.mutate_bias(&MutationConfig::default(), &mut rand::thread_rng());
         match nn.node_genes.get(&0) {
             Some(node) => assert_ne!(node.bias, 0.0),
             e => panic!("{:?}", e),
@@ -443,14 +1146,77 @@ mod test_neural_network_mutations {
     }
 
     #[test]
-    fn test_mutate_new_node() {
-        let input_nodes = vec![NodeGene::new(
-            0,
-            NodeType::Input,
-            0.0,
-            ActivationFunctions::None,
-            0.0,
-        )];
+    fn test_mutate_replace_weight() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let (mut nn, _) = random_network(1, 1, false, &mut rng);
+        let weights = WeightConfig {
+            weight_init: Distribution::Uniform {
+                low: 5.0,
+                high: 6.0,
+            },
+            ..WeightConfig::default()
+        };
+
+        nn.connection_genes[0].weight = -100.0;
+        nn.mutate_replace_weight(&weights, &mut rng);
+        let weight = nn.connection_genes[0].weight;
+        assert!((5.0..6.0).contains(&weight));
+    }
+
+    #[test]
+    fn test_mutate_scale_weights() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let (mut nn, _) = random_network(3, 2, false, &mut rng);
+        let before = NN::clone(&nn);
+        let weights = WeightConfig {
+            weight_scale: Distribution::Uniform {
+                low: 2.0,
+                high: 2.0,
+            },
+            ..WeightConfig::default()
+        };
+
+        nn.mutate_scale_weights(&weights, &mut rng);
+        for (conn, old) in nn.connection_genes.iter().zip(&before.connection_genes) {
+            assert_eq!(conn.weight, old.weight * 2.0);
+        }
+    }
+
+    #[test]
+    fn test_mutations_are_clamped() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let (mut nn, mut innovation) = random_network(3, 2, false, &mut rng);
+        let config = MutationConfig {
+            probabilities: vec![
+                (MutationOperator::Weight, 1.0),
+                (MutationOperator::Bias, 1.0),
+                (MutationOperator::ScaleWeights, 1.0),
+            ],
+            perturbation_power: 10.0,
+            weight_range: Some((-1.0, 1.0)),
+            bias_range: Some((-0.5, 0.5)),
+            ..MutationConfig::default()
+        };
+
+        for _ in 0..200 {
+            nn.mutate(&mut innovation, &config, &mut rng);
+        }
+        assert!(nn
+            .connection_genes
+            .iter()
+            .all(|conn| conn.weight.abs() <= 1.0));
+        assert!(nn.node_genes.values().all(|node| node.bias.abs() <= 0.5));
+    }
+
+    #[test]
+    fn test_mutate_new_node() {
+        let input_nodes = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
         let output_nodes = vec![NodeGene::new(
             1,
             NodeType::Output,
@@ -458,8 +1224,19 @@ mod test_neural_network_mutations {
             ActivationFunctions::None,
             0.0,
         )];
-        let mut nn = NN::new(input_nodes, output_nodes);
-        nn.mutate_new_node();
+        let mut innovation = InnovationTracker::new(2);
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.mutate_new_node(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
 
         assert_eq!(nn.connection_genes.len(), 3);
         assert_eq!(nn.node_genes_network.len(), 3);
@@ -471,6 +1248,64 @@ mod test_neural_network_mutations {
         }
     }
 
+    #[test]
+    fn test_mutate_new_node_shares_innovation() {
+        let input_nodes = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output_nodes = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut innovation = InnovationTracker::new(2);
+        let mut nn1 = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut nn2 = NN::clone(&nn1);
+
+        // both networks put a node between 0 and 1 in the same generation
+        nn1.mutate_new_node(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn2.mutate_new_node(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        assert!(nn1.node_genes.contains_key(&2));
+        assert!(nn2.node_genes.contains_key(&2));
+        let structure = |nn: &NN| {
+            nn.connection_genes
+                .iter()
+                .map(|conn| (conn.from, conn.to, conn.innovation))
+                .collect::<Vec<(u32, u32, u32)>>()
+        };
+        assert_eq!(structure(&nn1), structure(&nn2));
+
+        // the same split twice in one network can not reuse the node
+        nn1.node_genes_network = vec![vec![0], vec![1]];
+        nn1.mutate_new_node(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        assert!(nn1.node_genes.contains_key(&3));
+    }
+
     #[test]
     fn test_mutate_new_connection() {
         let input_nodes = vec![NodeGene::new(
@@ -487,19 +1322,138 @@ mod test_neural_network_mutations {
             ActivationFunctions::None,
             0.0,
         )];
-        let mut nn = NN::new(input_nodes, output_nodes);
-        nn.mutate_new_connection();
+        let mut innovation = InnovationTracker::new(2);
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.mutate_new_connection(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
 
         // it should not add if exists
         assert_eq!(nn.connection_genes.len(), 1);
 
         nn.connection_genes.pop();
 
-        nn.mutate_new_connection();
+        nn.mutate_new_connection(
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
 
         assert_eq!(nn.connection_genes.len(), 1);
     }
 
+    #[test]
+    fn test_mutate_new_connection_recurrent() {
+        let input_nodes = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output_nodes = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut innovation = InnovationTracker::new(2);
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.recurrent = true;
+
+        for _ in 0..50 {
+            nn.mutate_new_connection(
+                &mut innovation,
+                &WeightConfig::default(),
+                &mut rand::thread_rng(),
+            );
+        }
+
+        // the self loop on the output can be made, nothing goes to the input
+        assert!(nn.connection_genes.len() <= 2);
+        assert!(nn.connection_genes.iter().all(|conn| conn.to == 1));
+    }
+
+    #[test]
+    fn test_mutate_gate() {
+        let input_nodes = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output_nodes = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.mutate_gate(&mut rand::thread_rng());
+
+        // only the input is evaluated before the output
+        assert_eq!(nn.connection_genes[0].gater, 0);
+
+        nn.mutate_ungate(&mut rand::thread_rng());
+        assert_eq!(nn.connection_genes[0].gater, -1);
+    }
+
+    #[test]
+    fn test_mutate_remove_node_ungates() {
+        let input_nodes = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output_nodes = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let hidden_node = NodeGene::new(2, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
+
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.node_genes_network.insert(1, vec![2]);
+        nn.node_genes.insert(2, hidden_node);
+        nn.connection_genes[0].gater = 2;
+
+        nn.mutate_remove_node(&mut rand::thread_rng());
+        assert_eq!(nn.connection_genes[0].gater, -1);
+    }
+
     #[test]
     fn test_mutate_squash() {
         let input_nodes = vec![NodeGene::new(
@@ -517,8 +1471,14 @@ mod test_neural_network_mutations {
             0.0,
         )];
 
-        let mut nn = NN::new(input_nodes, output_nodes);
-        nn.mutate_squash();
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.mutate_squash(&mut rand::thread_rng());
 
         match nn.node_genes.get(&0) {
             Some(node1) => match nn.node_genes.get(&1) {
@@ -553,21 +1513,26 @@ mod test_neural_network_mutations {
 
         let hidden_node = NodeGene::new(2, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
 
-        let mut nn = NN::new(input_nodes, output_nodes);
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
         nn.node_genes_network.insert(1, vec![2]);
         nn.node_genes.insert(2, hidden_node);
 
-        let conn1 = ConnectionGene::new(0, 2, 0.0, -1);
-        let conn2 = ConnectionGene::new(2, 1, 0.0, -1);
+        let conn1 = ConnectionGene::new(0, 2, 0.0, -1, 1);
+        let conn2 = ConnectionGene::new(2, 1, 0.0, -1, 2);
         nn.connection_genes.push(conn1);
         nn.connection_genes.push(conn2);
 
-        nn.mutate_remove_node();
+        nn.mutate_remove_node(&mut rand::thread_rng());
         assert_eq!(nn.connection_genes.len(), 1);
         assert_eq!(nn.node_genes_network.len(), 2);
-        match nn.node_genes.get(&2) {
-            Some(node) => panic!("Should not give: {:?}", node),
-            _ => (),
+        if let Some(node) = nn.node_genes.get(&2) {
+            panic!("Should not give: {:?}", node);
         }
     }
 
@@ -588,10 +1553,249 @@ mod test_neural_network_mutations {
             0.0,
         )];
 
-        let mut nn = NN::new(input_nodes, output_nodes);
+        let mut nn = NN::new(
+            input_nodes,
+            output_nodes,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        nn.mutate_remove_connection(&mut rand::thread_rng());
+        assert_eq!(nn.connection_genes.len(), 1);
+        assert!(!nn.connection_genes[0].enabled);
+
+        // a disabled connection is not used, only the bias of the output is left
+        nn.node_genes.get_mut(&1).unwrap().bias = 0.5;
+        assert_eq!(nn.activate(&[2.0]), vec![0.5]);
+
+        nn.mutate_toggle_connection(&mut rand::thread_rng());
+        assert!(nn.connection_genes[0].enabled);
+        let weight = nn.connection_genes[0].weight;
+        assert_eq!(nn.activate(&[2.0]), vec![2.0 * weight + 0.5]);
+    }
+
+    #[test]
+    fn test_mutate_new_node_splits_connection() {
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let (mut nn, mut innovation) = random_network(1, 1, false, &mut rng);
+        let weight = nn.connection_genes[0].weight;
+        let before = nn.activate(&[1.5]);
+
+        nn.mutate_new_node(&mut innovation, &WeightConfig::default(), &mut rng);
+        assert_eq!(nn.connection_genes.len(), 3);
+        assert!(!nn.connection_genes[0].enabled);
+        assert_eq!(nn.connection_genes[1].weight, 1.0);
+        assert_eq!(nn.connection_genes[2].weight, weight);
+
+        // with an identity node without bias the network gives the same output
+        let id = nn.connection_genes[1].to;
+        let node = nn.node_genes.get_mut(&id).unwrap();
+        node.squash = ActivationFunctions::Identity;
+        node.bias = 0.0;
+        nn.reset_state();
+        assert_eq!(nn.activate(&[1.5]), before);
+    }
+
+    #[test]
+    fn test_crossover_inherits_disabled_genes() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let (mut parent1, _) = random_network(2, 2, false, &mut rng);
+        let parent2 = NN::clone(&parent1);
+        parent1.connection_genes[0].enabled = false;
+
+        let disabled = (0..1000)
+            .filter(|_| !NN::crossover(&parent1, &parent2, &mut rng).connection_genes[0].enabled)
+            .count();
+        assert!(disabled > 650 && disabled < 850);
+    }
+
+    fn random_network(
+        inputs: usize,
+        outputs: usize,
+        recurrent: bool,
+        rng: &mut dyn RngCore,
+    ) -> (NN, InnovationTracker) {
+        let mut innovation = InnovationTracker::new((inputs + outputs) as u32);
+        let input: Vec<NodeGene> = (0..inputs)
+            .map(|id| {
+                NodeGene::new(
+                    id as u32,
+                    NodeType::Input,
+                    0.0,
+                    ActivationFunctions::None,
+                    0.0,
+                )
+            })
+            .collect();
+        let output: Vec<NodeGene> = (inputs..inputs + outputs)
+            .map(|id| {
+                NodeGene::new(
+                    id as u32,
+                    NodeType::Output,
+                    0.0,
+                    ActivationFunctions::None,
+                    0.0,
+                )
+            })
+            .collect();
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut innovation,
+            &WeightConfig::default(),
+            rng,
+        );
+        nn.recurrent = recurrent;
+        (nn, innovation)
+    }
+
+    #[test]
+    fn test_mutate_config() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let (mut nn, mut innovation) = random_network(3, 2, false, &mut rng);
+
+        // nothing happens when every probability is zero
+        let mut config = MutationConfig::default();
+        config.probabilities.iter_mut().for_each(|(_, p)| *p = 0.0);
+        let before = NN::clone(&nn);
+        for _ in 0..50 {
+            nn.mutate(&mut innovation, &config, &mut rng);
+        }
+        assert_eq!(nn, before);
+
+        // the structure stops growing at the limits
+        config.set_probability(MutationOperator::AddNode, 1.0);
+        config.set_probability(MutationOperator::AddConnection, 1.0);
+        config.max_nodes = Some(8);
+        config.max_connections = Some(12);
+        for _ in 0..50 {
+            nn.mutate(&mut innovation, &config, &mut rng);
+        }
+        assert!(nn.node_genes.len() > before.node_genes.len());
+        assert!(nn.node_genes.len() <= 8);
+        assert!(nn.connection_genes.len() <= 12);
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let (mut nn, mut innovation) = random_network(3, 2, false, &mut rng);
+        let mut config = MutationConfig::default();
+        let genes = nn.connection_genes.len();
+
+        // one gene short of room for a new node
+        config.max_connections = Some(genes + 1);
+        nn.apply_mutation(
+            MutationOperator::AddNode,
+            &mut innovation,
+            &config,
+            &mut rng,
+        );
+        assert_eq!(nn.connection_genes.len(), genes);
+
+        // a new node fills the limit exactly, even though it disables the split connection
+        config.max_connections = Some(genes + 2);
+        nn.apply_mutation(
+            MutationOperator::AddNode,
+            &mut innovation,
+            &config,
+            &mut rng,
+        );
+        assert_eq!(nn.connection_genes.len(), genes + 2);
+
+        // at the limit nothing is added
+        nn.apply_mutation(
+            MutationOperator::AddNode,
+            &mut innovation,
+            &config,
+            &mut rng,
+        );
+        nn.apply_mutation(
+            MutationOperator::AddConnection,
+            &mut innovation,
+            &config,
+            &mut rng,
+        );
+        assert_eq!(nn.connection_genes.len(), genes + 2);
+    }
+
+    #[test]
+    fn test_self_adaptive_mutation() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let (mut nn, mut innovation) = random_network(3, 2, false, &mut rng);
+        let mut config = MutationConfig::default();
+
+        // the step is left alone without self-adaptation
+        for _ in 0..20 {
+            nn.mutate(&mut innovation, &config, &mut rng);
+        }
+        assert_eq!(nn.mutation_step, 1.0);
+
+        config.self_adaptation = Some(SelfAdaptation::default());
+        nn.mutate(&mut innovation, &config, &mut rng);
+        assert_ne!(nn.mutation_step, 1.0);
+
+        // a step of zero leaves the weights as they are
+        config.probabilities = vec![(MutationOperator::Weight, 1.0)];
+        config.replace_weight_probability = 0.0;
+        config.self_adaptation = Some(SelfAdaptation {
+            learning_rate: 0.0,
+            min_step: 0.0,
+            max_step: 0.0,
+        });
+        let before = NN::clone(&nn);
+        nn.mutate(&mut innovation, &config, &mut rng);
+        assert_eq!(nn.mutation_step, 0.0);
+        assert_eq!(nn.connection_genes, before.connection_genes);
+
+        // the child gets the geometric mean of the steps
+        let mut parent1 = NN::clone(&before);
+        let mut parent2 = NN::clone(&before);
+        parent1.mutation_step = 0.5;
+        parent2.mutation_step = 2.0;
+        assert_eq!(
+            NN::crossover(&parent1, &parent2, &mut rng).mutation_step,
+            1.0
+        );
+    }
 
-        nn.mutate_remove_connection();
-        assert_eq!(nn.connection_genes.len(), 0);
+    proptest::proptest! {
+        #[test]
+        fn prop_mutations_keep_network_valid(
+            methods in proptest::collection::vec(0..MutationOperator::ALL.len(), 1..300),
+            inputs in 1..5usize,
+            outputs in 1..4usize,
+            recurrent in proptest::bool::ANY,
+            seed in proptest::num::u64::ANY,
+        ) {
+            // a failing case can be run again with the same random numbers
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let (mut nn, mut innovation) = random_network(inputs, outputs, recurrent, &mut rng);
+            for method in methods {
+                nn.apply_mutation(MutationOperator::ALL[method], &mut innovation, &MutationConfig::default(), &mut rng);
+                proptest::prop_assert_eq!(nn.validate(), Ok(()), "after {:?}", MutationOperator::ALL[method]);
+            }
+            let outputs_len = nn.try_activate(&vec![1.0; inputs]).map(|values| values.len());
+            proptest::prop_assert_eq!(outputs_len, Ok(outputs));
+        }
+
+        #[test]
+        fn prop_crossover_keeps_network_valid(
+            methods in proptest::collection::vec((0..MutationOperator::ALL.len(), 0..MutationOperator::ALL.len()), 1..100),
+            recurrent in proptest::bool::ANY,
+            seed in proptest::num::u64::ANY,
+        ) {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let (mut nn1, mut innovation) = random_network(3, 2, recurrent, &mut rng);
+            let mut nn2 = NN::clone(&nn1);
+            for (method1, method2) in methods {
+                nn1.apply_mutation(MutationOperator::ALL[method1], &mut innovation, &MutationConfig::default(), &mut rng);
+                nn2.apply_mutation(MutationOperator::ALL[method2], &mut innovation, &MutationConfig::default(), &mut rng);
+            }
+            proptest::prop_assert_eq!(NN::crossover(&nn1, &nn2, &mut rng).validate(), Ok(()));
+            proptest::prop_assert_eq!(NN::crossover(&nn2, &nn1, &mut rng).validate(), Ok(()));
+        }
     }
 }
 
@@ -613,7 +1817,13 @@ mod test_neural_network {
             NodeGene::new(5, NodeType::Output, 0.0, ActivationFunctions::None, 0.0),
         ];
 
-        let nn = NN::new(input, output);
+        let nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
 
         println!("{:?}", nn.connection_genes);
 
@@ -646,7 +1856,13 @@ mod test_neural_network {
             0.0,
         )];
 
-        let nn = NN::new(input_layer, output_layer);
+        let nn = NN::new(
+            input_layer,
+            output_layer,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
 
         let nn_clone = NN::clone(&nn);
 
@@ -658,17 +1874,17 @@ mod test_neural_network {
 
     #[test]
     fn test_get_output() {
-        let input_layer = vec![
+        let input_layer = [
             NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 1.0),
             NodeGene::new(1, NodeType::Input, 0.0, ActivationFunctions::None, 2.0),
         ];
 
-        let output_layer = vec![
+        let output_layer = [
             NodeGene::new(2, NodeType::Output, 0.0, ActivationFunctions::None, 0.0),
             NodeGene::new(3, NodeType::Output, 0.0, ActivationFunctions::None, 0.0),
         ];
 
-        let hidden_layer = vec![NodeGene::new(
+        let hidden_layer = [NodeGene::new(
             4,
             NodeType::Output,
             1.0,
@@ -686,20 +1902,22 @@ mod test_neural_network {
         node_genes.insert(4, hidden_layer[0]);
 
         let connection_genes = vec![
-            ConnectionGene::new(0, 2, 1.0, -1),
-            ConnectionGene::new(0, 3, 1.0, -1),
-            ConnectionGene::new(0, 4, 2.0, -1),
-            ConnectionGene::new(1, 2, 1.0, -1),
-            ConnectionGene::new(1, 3, 1.0, -1),
-            ConnectionGene::new(4, 3, 2.0, -1),
+            ConnectionGene::new(0, 2, 1.0, -1, 0),
+            ConnectionGene::new(0, 3, 1.0, -1, 1),
+            ConnectionGene::new(0, 4, 2.0, -1, 2),
+            ConnectionGene::new(1, 2, 1.0, -1, 3),
+            ConnectionGene::new(1, 3, 1.0, -1, 4),
+            ConnectionGene::new(4, 3, 2.0, -1, 5),
         ];
 
         let mut nn = NN {
-            node_genes_network: node_genes_network,
-            node_genes: node_genes,
-            connection_genes: connection_genes,
+            node_genes_network,
+            node_genes,
+            connection_genes,
             fitness: 0.0,
-            next_id: 0,
+            evaluation_order: None,
+            recurrent: false,
+            mutation_step: 1.0,
         };
 
         let res = nn.get_output();
@@ -711,22 +1929,406 @@ mod test_neural_network {
             None => panic!("It should return a value"),
         };
 
+        // the hidden node gives (1.0 + 1.0 * 2.0) + 1.0 = 4.0 with its bias
         match nn.node_genes.get(&3) {
-            Some(node) => assert_eq!(node.value, 6.0),
+            Some(node) => assert_eq!(node.value, 11.0),
             None => panic!("It should return a value"),
         };
     }
 
+    #[test]
+    fn test_align_genes() {
+        let mut innovation = InnovationTracker::new(0);
+        let mut nn1 = NN::new(
+            vec![],
+            vec![],
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut nn2 = NN::new(
+            vec![],
+            vec![],
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        let shared = ConnectionGene::new(0, 1, 1.0, -1, 1);
+        let disjoint = ConnectionGene::new(0, 2, 1.0, -1, 2);
+        let other_disjoint = ConnectionGene::new(2, 1, 1.0, -1, 3);
+        let excess = ConnectionGene::new(1, 2, 1.0, -1, 4);
+
+        nn1.connection_genes = vec![excess, shared, disjoint];
+        nn2.connection_genes = vec![other_disjoint, shared];
+
+        let alignment = NN::align_genes(&nn1, &nn2);
+
+        assert_eq!(alignment.matching, vec![(shared, shared)]);
+        assert_eq!(alignment.disjoint, (vec![disjoint], vec![other_disjoint]));
+        assert_eq!(alignment.excess, (vec![excess], vec![]));
+    }
+
+    #[test]
+    fn test_crossover() {
+        let input_layer = vec![
+            NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+            NodeGene::new(1, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+        ];
+        let output_layer = vec![NodeGene::new(
+            2,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+
+        let mut innovation = InnovationTracker::new(3);
+        let mut fitter = NN::new(
+            input_layer,
+            output_layer,
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut other = NN::clone(&fitter);
+
+        // the fitter parent has a hidden node the other parent does not have
+        let hidden = NodeGene::new(3, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
+        fitter.node_genes.insert(3, hidden);
+        fitter.node_genes_network.insert(1, vec![3]);
+        let conn1 = ConnectionGene::new(0, 3, 1.0, -1, innovation.get_connection_innovation(0, 3));
+        let conn2 = ConnectionGene::new(3, 2, 1.0, -1, innovation.get_connection_innovation(3, 2));
+        fitter.connection_genes.push(conn1);
+        fitter.connection_genes.push(conn2);
+
+        // the other parent has a connection the fitter parent does not have
+        other.connection_genes.remove(0);
+
+        fitter.fitness = 10.0;
+        other.fitness = 0.0;
+
+        let child = NN::crossover(&other, &fitter, &mut rand::thread_rng());
+
+        // every gene of the fitter parent is inherited, nothing else
+        assert_eq!(child.connection_genes.len(), fitter.connection_genes.len());
+        for (child_conn, fitter_conn) in child.connection_genes.iter().zip(&fitter.connection_genes)
+        {
+            assert_eq!(child_conn.innovation, fitter_conn.innovation);
+        }
+        assert_eq!(child.node_genes_network, fitter.node_genes_network);
+        assert!(child.node_genes.contains_key(&3));
+        assert_eq!(child.fitness, 0.0);
+    }
+
+    #[test]
+    fn test_compatibility_distance() {
+        let mut innovation = InnovationTracker::new(0);
+        let mut nn1 = NN::new(
+            vec![],
+            vec![],
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut nn2 = NN::new(
+            vec![],
+            vec![],
+            &mut innovation,
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        nn1.connection_genes = vec![
+            ConnectionGene::new(0, 1, 1.0, -1, 1),
+            ConnectionGene::new(0, 2, 1.0, -1, 2),
+            ConnectionGene::new(1, 2, 1.0, -1, 4),
+        ];
+        nn2.connection_genes = vec![
+            ConnectionGene::new(0, 1, 3.0, -1, 1),
+            ConnectionGene::new(2, 1, 1.0, -1, 3),
+        ];
+
+        let config = SpeciationConfig {
+            c1: 1.0,
+            c2: 2.0,
+            c3: 0.5,
+            ..SpeciationConfig::default()
+        };
+
+        // one excess, two disjoint and a weight difference of 2.0
+        assert_eq!(NN::compatibility_distance(&nn1, &nn2, &config), 6.0);
+        assert_eq!(NN::compatibility_distance(&nn1, &nn1, &config), 0.0);
+    }
+
+    #[test]
+    fn test_evaluation_order_is_a_cache() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.5,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.connection_genes[0].weight = 2.0;
+        let copy = NN::clone(&nn);
+
+        assert_eq!(nn.activate(&[1.0]), vec![2.5]);
+        assert!(nn.evaluation_order.is_some());
+        nn.reset_state();
+        assert_eq!(nn, copy);
+
+        // a new weight is used without making the order again
+        nn.connection_genes[0].weight = -1.0;
+        assert_eq!(nn.activate(&[1.0]), vec![-0.5]);
+    }
+
+    #[test]
+    fn test_get_evaluation_order() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        // the hidden nodes are in the same layer, but 3 gets its input from 2
+        for id in [2, 3] {
+            let node = NodeGene::new(id, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
+            nn.node_genes.insert(id, node);
+        }
+        nn.node_genes_network.insert(1, vec![3, 2]);
+        nn.connection_genes = vec![
+            ConnectionGene::new(3, 1, 1.0, -1, 0),
+            ConnectionGene::new(2, 3, 1.0, -1, 1),
+            ConnectionGene::new(0, 2, 1.0, -1, 2),
+        ];
+
+        assert_eq!(nn.get_evaluation_order(), vec![0, 2, 3, 1]);
+        assert_eq!(
+            nn.evaluation_order
+                .as_ref()
+                .map(|order| order.nodes.clone()),
+            Some(vec![0, 2, 3, 1])
+        );
+
+        // the order is kept until it is invalidated
+        nn.connection_genes.pop();
+        assert_eq!(nn.get_evaluation_order(), vec![0, 2, 3, 1]);
+        nn.invalidate_evaluation_order();
+        assert_eq!(nn.get_evaluation_order(), vec![0, 2, 3, 1]);
+        nn.connection_genes.remove(1);
+        nn.invalidate_evaluation_order();
+        assert_eq!(nn.get_evaluation_order(), vec![0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_get_output_applies_squash_once() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            1.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        // a hidden node with two outgoing connections, added in the "wrong" order
+        let hidden = NodeGene::new(2, NodeType::Hidden, 1.0, ActivationFunctions::ReLU, 0.0);
+        nn.node_genes.insert(2, hidden);
+        nn.node_genes_network.insert(1, vec![2]);
+        nn.connection_genes = vec![
+            ConnectionGene::new(2, 1, 2.0, -1, 0),
+            ConnectionGene::new(2, 1, 3.0, -1, 1),
+            ConnectionGene::new(0, 2, 1.0, -1, 2),
+        ];
+
+        nn.get_output();
+
+        // relu(1.0 + 1.0) = 2.0 goes out through both connections
+        match nn.node_genes.get(&1) {
+            Some(node) => assert_eq!(node.value, 10.0),
+            None => panic!("It should return a value"),
+        };
+    }
+
+    #[test]
+    fn test_recurrent_update() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.recurrent = true;
+        nn.connection_genes[0].weight = 1.0;
+        nn.connection_genes
+            .push(ConnectionGene::new(1, 1, 1.0, -1, 1));
+
+        // the output remembers its activation from the last update
+        for expected in [1.0, 2.0, 3.0] {
+            nn.update(vec![1.0]);
+            match nn.node_genes.get(&1) {
+                Some(node) => assert_eq!(node.activation, expected),
+                e => panic!("{:?}", e),
+            }
+        }
+
+        nn.reset_state();
+        nn.update(vec![1.0]);
+        match nn.node_genes.get(&1) {
+            Some(node) => assert_eq!(node.activation, 1.0),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_gated_connection() {
+        let input = vec![
+            NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+            NodeGene::new(1, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+        ];
+        let output = vec![NodeGene::new(
+            2,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(3),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+
+        // the hidden node gates the connection from 0 to 2, so it has to be
+        // evaluated before the output even without a connection to it
+        let hidden = NodeGene::new(3, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
+        nn.node_genes.insert(3, hidden);
+        nn.node_genes_network.insert(1, vec![3]);
+        nn.connection_genes = vec![
+            ConnectionGene::new(0, 2, 2.0, 3, 0),
+            ConnectionGene::new(1, 3, 1.0, -1, 1),
+        ];
+
+        for (input, expected) in [(0.0, 0.0), (1.0, 2.0), (3.0, 6.0)] {
+            nn.update(vec![1.0, input]);
+            match nn.node_genes.get(&2) {
+                Some(node) => assert_eq!(node.activation, expected),
+                e => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_crossover_removes_missing_gater() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut fitter = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut other = NN::clone(&fitter);
+        fitter.fitness = 10.0;
+
+        // the gater only exists in the other parent
+        let hidden = NodeGene::new(2, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0);
+        other.node_genes.insert(2, hidden);
+        other.node_genes_network.insert(1, vec![2]);
+        other.connection_genes[0].gater = 2;
+
+        for _ in 0..10 {
+            let child = NN::crossover(&fitter, &other, &mut rand::thread_rng());
+            assert_eq!(child.connection_genes[0].gater, -1);
+        }
+    }
+
     #[test]
     fn test_calculate_fitness() {
         let node_genes_network = vec![vec![0, 1], vec![3], vec![2]];
 
         let nn = NN {
-            node_genes_network: node_genes_network,
+            node_genes_network,
             node_genes: HashMap::new(),
-            connection_genes: vec![ConnectionGene::new(1, 0, 0.0, -1)],
+            connection_genes: vec![ConnectionGene::new(1, 0, 0.0, -1, 0)],
             fitness: 10.0,
-            next_id: 0,
+            evaluation_order: None,
+            recurrent: false,
+            mutation_step: 1.0,
         };
 
         // For now this should be ok, but should make a better fitness function
@@ -747,14 +2349,23 @@ mod test_neural_network {
 
         let hidden = NodeGene::new(4, NodeType::Hidden, 1.0, ActivationFunctions::None, 1.0);
 
-        let con1 = ConnectionGene::new(0, 4, 1.0, -1);
-        let con2 = ConnectionGene::new(4, 2, 1.0, -1);
+        let con1 = ConnectionGene::new(0, 4, 1.0, -1, 4);
+        let con2 = ConnectionGene::new(4, 2, 1.0, -1, 5);
 
-        let mut nn = NN::new(input, output);
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
         nn.node_genes_network.insert(1, vec![4]);
         nn.node_genes.insert(4, hidden);
         nn.connection_genes.insert(2, con1);
         nn.connection_genes.push(con2);
+        for conn in nn.connection_genes.iter_mut() {
+            conn.weight = 1.0;
+        }
         let res = nn.update(vec![1.0, 2.0]);
 
         assert_eq!(res, 0);
@@ -771,11 +2382,235 @@ mod test_neural_network {
         }
     }
 
+    #[test]
+    fn test_activate() {
+        let input = vec![
+            NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+            NodeGene::new(1, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+        ];
+        let output = vec![
+            NodeGene::new(2, NodeType::Output, 0.0, ActivationFunctions::None, 0.0),
+            NodeGene::new(3, NodeType::Output, 1.0, ActivationFunctions::None, 0.0),
+        ];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(4),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        for conn in nn.connection_genes.iter_mut() {
+            conn.weight = 1.0;
+        }
+
+        assert_eq!(nn.activate(&[1.0, 2.0]), vec![3.0, 4.0]);
+        assert_eq!(nn.activate_argmax(&[1.0, 2.0]), 1);
+        assert_eq!(
+            nn.activate_multi_label(&[-1.0, 0.5], 0.0),
+            vec![false, true]
+        );
+
+        // the output with a much higher activation is (almost) always picked
+        nn.connection_genes[1].weight = 100.0;
+        assert_eq!(nn.activate_softmax(&[1.0, 0.0], &mut rand::thread_rng()), 1);
+    }
+
+    #[test]
+    fn test_try_activate() {
+        let input = vec![
+            NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+            NodeGene::new(1, NodeType::Input, 0.0, ActivationFunctions::None, 0.0),
+        ];
+        let output = vec![NodeGene::new(
+            2,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(3),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        for conn in nn.connection_genes.iter_mut() {
+            conn.weight = 1.0;
+        }
+
+        assert_eq!(nn.try_activate(&[1.0, 2.0]), Ok(vec![3.0]));
+        assert_eq!(nn.try_update(vec![1.0, 2.0]), Ok(0));
+        assert_eq!(
+            nn.try_activate(&[1.0]),
+            Err(NnError::InputSizeMismatch {
+                expected: 2,
+                got: 1
+            })
+        );
+
+        nn.connection_genes
+            .push(ConnectionGene::new(5, 2, 1.0, -1, 2));
+        assert_eq!(
+            nn.try_activate(&[1.0, 2.0]),
+            Err(NnError::DanglingConnection { from: 5, to: 2 })
+        );
+        nn.connection_genes.pop();
+
+        nn.node_genes_network[1].push(7);
+        assert_eq!(nn.try_activate(&[1.0, 2.0]), Err(NnError::MissingNode(7)));
+        nn.node_genes_network[1].pop();
+
+        // the nodes in a cycle are never evaluated
+        nn.node_genes.insert(
+            3,
+            NodeGene::new(3, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0),
+        );
+        nn.node_genes.insert(
+            4,
+            NodeGene::new(4, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0),
+        );
+        nn.connection_genes
+            .push(ConnectionGene::new(3, 4, 1.0, -1, 3));
+        nn.connection_genes
+            .push(ConnectionGene::new(4, 3, 1.0, -1, 4));
+        nn.invalidate_evaluation_order();
+        assert_eq!(nn.try_activate(&[1.0, 2.0]), Err(NnError::CycleDetected));
+
+        let mut empty = NN::new(
+            vec![],
+            vec![],
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        assert_eq!(empty.try_update(vec![]), Err(NnError::EmptyNetwork));
+        assert_eq!(
+            empty.try_mutate(
+                &mut InnovationTracker::new(0),
+                &MutationConfig::default(),
+                &mut rand::thread_rng()
+            ),
+            Err(NnError::EmptyNetwork)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        assert_eq!(nn.validate(), Ok(()));
+
+        nn.connection_genes
+            .push(ConnectionGene::new(0, 1, 1.0, -1, 0));
+        assert_eq!(
+            nn.validate(),
+            Err(NnError::DuplicateConnection { from: 0, to: 1 })
+        );
+        // a broken network is not mutated
+        let broken = NN::clone(&nn);
+        assert_eq!(
+            nn.try_mutate(
+                &mut InnovationTracker::new(2),
+                &MutationConfig::default(),
+                &mut rand::thread_rng()
+            ),
+            Err(NnError::DuplicateConnection { from: 0, to: 1 })
+        );
+        assert_eq!(nn, broken);
+        nn.connection_genes.pop();
+
+        nn.connection_genes
+            .push(ConnectionGene::new(1, 0, 1.0, -1, 1));
+        assert_eq!(nn.validate(), Err(NnError::InputHasIncoming(0)));
+        nn.connection_genes.pop();
+
+        nn.node_genes.insert(
+            2,
+            NodeGene::new(2, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0),
+        );
+        assert_eq!(nn.validate(), Err(NnError::MisplacedNode(2)));
+        nn.node_genes_network.insert(1, vec![2]);
+        assert_eq!(nn.validate(), Ok(()));
+
+        nn.connection_genes
+            .push(ConnectionGene::new(1, 2, 1.0, -1, 2));
+        assert_eq!(nn.validate(), Err(NnError::OutputHasOutgoing(1)));
+
+        // feeding the output back is allowed in a recurrent network
+        nn.recurrent = true;
+        assert_eq!(nn.validate(), Ok(()));
+
+        // two hidden nodes in the same layer feeding each other
+        nn.recurrent = false;
+        nn.connection_genes.pop();
+        nn.node_genes.insert(
+            3,
+            NodeGene::new(3, NodeType::Hidden, 0.0, ActivationFunctions::None, 0.0),
+        );
+        nn.node_genes_network[1].push(3);
+        nn.connection_genes
+            .push(ConnectionGene::new(2, 3, 1.0, -1, 3));
+        nn.connection_genes
+            .push(ConnectionGene::new(3, 2, 1.0, -1, 4));
+        assert_eq!(nn.validate(), Err(NnError::CycleDetected));
+        assert!(nn.evaluation_order.is_none());
+
+        // a disabled cycle is still a cycle, whether the network was activated or not
+        nn.connection_genes[2].enabled = false;
+        assert_eq!(nn.validate(), Err(NnError::CycleDetected));
+        nn.activate(&[1.0]);
+        assert!(nn.evaluation_order.is_some());
+        assert_eq!(nn.validate(), Err(NnError::CycleDetected));
+    }
+
+    #[test]
+    fn test_argmax() {
+        assert_eq!(NN::argmax(&[1.0, 3.0, 2.0]), 1);
+        assert_eq!(NN::argmax(&[-1.0, -3.0]), 0);
+        assert_eq!(NN::argmax(&[2.0, 2.0]), 0);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let probabilities = NN::softmax(&[1.0, 1.0]);
+        assert_eq!(probabilities, vec![0.5, 0.5]);
+
+        let probabilities = NN::softmax(&[1000.0, 0.0, -5.0]);
+        assert_eq!(probabilities[0], 1.0);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
     #[test]
     fn test_reset_gene_value() {
         let node = NodeGene::new(0, NodeType::Input, 0.0, ActivationFunctions::None, 1.0);
 
-        let mut nn = NN::new(vec![node], vec![]);
+        let mut nn = NN::new(
+            vec![node],
+            vec![],
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
         nn.reset_gene_value();
         match nn.node_genes.get(&0) {
             Some(node) => assert_eq!(node.value, 0.0),
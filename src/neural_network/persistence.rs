@@ -0,0 +1,236 @@
+use super::error::NnError;
+use super::nn::NN;
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// A saved network is a JSON object with the format version and the network:
+//
+// {
+//   "version": 3,
+//   "network": {
+//     "node_genes_network": [[0, 1], [2]],
+//     "node_genes": { "0": { "id": 0, "node_type": "Input", "bias": 0.0, "squash": "None", ... }, ... },
+//     "connection_genes": [{ "from": 0, "to": 2, "weight": 0.5, "gater": -1, "innovation": 0, "enabled": true }, ...],
+//     "fitness": 0.0,
+//     "recurrent": false,
+//     "mutation_step": 1.0
+//   }
+// }
+//
+// When the genome gets new fields, FORMAT_VERSION is increased and it gets a step
+// in UPGRADES that turns the previous version into the new one, so old files keep loading
+pub const FORMAT_VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    // the file is from a newer version of the program, or has no version
+    UnsupportedVersion(Option<u64>),
+    // the file could be read, but the network is broken
+    InvalidNetwork(NnError),
+    // a binary file or checkpoint that does not start with the magic bytes
+    BadMagic,
+    // a byte in a binary file that is not a node type or activation function
+    InvalidTag(u8),
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "could not read or write the file: {}", e),
+            PersistenceError::Json(e) => write!(f, "invalid json: {}", e),
+            PersistenceError::Bincode(e) => write!(f, "invalid checkpoint: {}", e),
+            PersistenceError::UnsupportedVersion(Some(version)) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            PersistenceError::UnsupportedVersion(None) => write!(f, "missing format version"),
+            PersistenceError::InvalidNetwork(e) => write!(f, "invalid network: {}", e),
+            PersistenceError::BadMagic => write!(f, "not a network or checkpoint file"),
+            PersistenceError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+impl From<std::io::Error> for PersistenceError {
+    fn from(e: std::io::Error) -> Self {
+        PersistenceError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PersistenceError {
+    fn from(e: serde_json::Error) -> Self {
+        PersistenceError::Json(e)
+    }
+}
+
+impl From<bincode::Error> for PersistenceError {
+    fn from(e: bincode::Error) -> Self {
+        PersistenceError::Bincode(e)
+    }
+}
+
+// UPGRADES[i] moves a network from version i + 1 to version i + 2
+const UPGRADES: [fn(Value) -> Value; FORMAT_VERSION as usize - 1] =
+    [add_mutation_step, enable_connections];
+
+fn add_mutation_step(mut network: Value) -> Value {
+    // version 2 gave every genome its own mutation step
+    network["mutation_step"] = json!(1.0);
+    network
+}
+
+fn enable_connections(mut network: Value) -> Value {
+    // version 3 could disable connections, before that they were all used
+    if let Some(connections) = network["connection_genes"].as_array_mut() {
+        for conn in connections {
+            conn["enabled"] = json!(true);
+        }
+    }
+    network
+}
+
+impl NN {
+    pub fn to_json(&self) -> Result<String, PersistenceError> {
+        let saved = json!({
+            "version": FORMAT_VERSION,
+            "network": serde_json::to_value(self)?,
+        });
+        Ok(serde_json::to_string_pretty(&saved)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<NN, PersistenceError> {
+        let mut saved: Value = serde_json::from_str(json)?;
+        let version = match saved.get("version").and_then(|version| version.as_u64()) {
+            Some(version) if version >= 1 && version <= FORMAT_VERSION as u64 => version as u32,
+            version => return Err(PersistenceError::UnsupportedVersion(version)),
+        };
+
+        let mut network = saved["network"].take();
+        for upgrade in &UPGRADES[version as usize - 1..] {
+            network = upgrade(network);
+        }
+
+        let nn: NN = serde_json::from_value(network)?;
+        nn.validate().map_err(PersistenceError::InvalidNetwork)?;
+        Ok(nn)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<NN, PersistenceError> {
+        NN::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test_persistence {
+    use super::super::activation_functions::ActivationFunctions;
+    use super::super::genes::{NodeGene, NodeType};
+    use super::super::innovation::InnovationTracker;
+    use super::super::mutation::WeightConfig;
+    use super::*;
+
+    fn network() -> NN {
+        let input = vec![
+            NodeGene::new(0, NodeType::Input, 0.1, ActivationFunctions::None, 0.0),
+            NodeGene::new(1, NodeType::Input, 0.2, ActivationFunctions::ReLU, 0.0),
+        ];
+        let output = vec![NodeGene::new(
+            2,
+            NodeType::Output,
+            -0.3,
+            ActivationFunctions::Logistics,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(3),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.connection_genes[1].gater = 0;
+        nn.fitness = 4.0;
+        nn.recurrent = true;
+        nn
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let nn = network();
+        let loaded = NN::from_json(&nn.to_json().unwrap()).unwrap();
+        assert_eq!(loaded, nn);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("nn_test_{}.json", std::process::id()));
+        let nn = network();
+
+        nn.save(&path).unwrap();
+        let loaded = NN::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, nn);
+        assert!(matches!(NN::load(&path), Err(PersistenceError::Io(_))));
+    }
+
+    #[test]
+    fn test_load_version() {
+        let mut saved: Value = serde_json::from_str(&network().to_json().unwrap()).unwrap();
+        assert_eq!(saved["version"], FORMAT_VERSION);
+
+        saved["version"] = json!(FORMAT_VERSION + 1);
+        assert!(matches!(
+            NN::from_json(&saved.to_string()),
+            Err(PersistenceError::UnsupportedVersion(_))
+        ));
+
+        saved.as_object_mut().unwrap().remove("version");
+        assert!(matches!(
+            NN::from_json(&saved.to_string()),
+            Err(PersistenceError::UnsupportedVersion(None))
+        ));
+    }
+
+    #[test]
+    fn test_load_optional_fields() {
+        // the runtime state and the recurrent flag can be left out
+        let json = r#"{
+            "version": 1,
+            "network": {
+                "node_genes_network": [[0], [1]],
+                "node_genes": {
+                    "0": { "id": 0, "node_type": "Input", "bias": 0.0, "squash": "None" },
+                    "1": { "id": 1, "node_type": "Output", "bias": 1.0, "squash": "None" }
+                },
+                "connection_genes": [{ "from": 0, "to": 1, "weight": 2.0, "gater": -1, "innovation": 0 }],
+                "fitness": 0.0
+            }
+        }"#;
+        let mut nn = NN::from_json(json).unwrap();
+        assert!(!nn.recurrent);
+        assert_eq!(nn.mutation_step, 1.0);
+        assert!(nn.connection_genes[0].enabled);
+        assert_eq!(nn.activate(&[1.0]), vec![3.0]);
+    }
+
+    #[test]
+    fn test_load_invalid_network() {
+        let mut nn = network();
+        nn.node_genes.remove(&2);
+        assert!(matches!(
+            NN::from_json(&nn.to_json().unwrap()),
+            Err(PersistenceError::InvalidNetwork(NnError::MissingNode(2)))
+        ));
+    }
+}
@@ -0,0 +1,330 @@
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+// Selection methods, see https://en.wikipedia.org/wiki/Selection_(genetic_algorithm)
+// A strategy gets the fitness of every candidate and returns the index of the
+// selected candidates, the same candidate can be selected more than once
+pub trait SelectionStrategy {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize>;
+
+    // the strategy as something that can be saved in a checkpoint,
+    // a strategy that returns None is replaced by the default when resuming
+    fn config(&self) -> Option<SelectionConfig> {
+        None
+    }
+}
+
+// The built in strategies and their parameters
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum SelectionConfig {
+    Tournament { size: usize },
+    Roulette,
+    Rank,
+    StochasticUniversalSampling,
+    Truncation { fraction: f32 },
+}
+
+impl SelectionConfig {
+    pub fn strategy(&self) -> Box<dyn SelectionStrategy> {
+        match *self {
+            SelectionConfig::Tournament { size } => Box::new(TournamentSelection { size }),
+            SelectionConfig::Roulette => Box::new(RouletteSelection),
+            SelectionConfig::Rank => Box::new(RankSelection),
+            SelectionConfig::StochasticUniversalSampling => Box::new(StochasticUniversalSampling),
+            SelectionConfig::Truncation { fraction } => Box::new(TruncationSelection { fraction }),
+        }
+    }
+}
+
+// the best of `size` random candidates is selected
+pub struct TournamentSelection {
+    pub size: usize,
+}
+
+// fitness proportionate selection
+pub struct RouletteSelection;
+
+// the chance of being selected is proportionate to the rank instead of the fitness
+pub struct RankSelection;
+
+// like roulette, but with evenly spaced pointers so the result is close to the expected
+pub struct StochasticUniversalSampling;
+
+// only the best fraction of the candidates can be selected
+pub struct TruncationSelection {
+    pub fraction: f32,
+}
+
+// orders two fitness values from worst to best, a NaN fitness is worse than any other
+pub fn compare_fitness(a: f32, b: f32) -> Ordering {
+    let rank = |fitness: f32| {
+        if fitness.is_nan() {
+            f32::NEG_INFINITY
+        } else {
+            fitness
+        }
+    };
+    rank(a).total_cmp(&rank(b))
+}
+
+fn shift_fitness(fitness: &[f32]) -> Vec<f32> {
+    // the fitness can be negative, so it is shifted to make the worst 0,
+    // f32::min skips a NaN fitness and it gets 0 as well so it is never selected
+    let min = fitness.iter().cloned().fold(f32::INFINITY, f32::min);
+    fitness
+        .iter()
+        .map(|f| if f.is_nan() { 0.0 } else { f - min })
+        .collect()
+}
+
+fn spin(weights: &[f32], point: f32) -> usize {
+    // finds where on the wheel the point is
+    let mut total = 0.0;
+    for (i, weight) in weights.iter().enumerate() {
+        total += weight;
+        if point < total {
+            return i;
+        }
+    }
+    weights.len() - 1
+}
+
+fn roulette(weights: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+        // every candidate is as good as the other
+        return (0..count)
+            .map(|_| rng.gen_range(0..weights.len()))
+            .collect();
+    }
+    (0..count)
+        .map(|_| spin(weights, rng.gen::<f32>() * total))
+        .collect()
+}
+
+impl SelectionStrategy for TournamentSelection {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+        if fitness.is_empty() {
+            return vec![];
+        }
+        let mut selected = vec![];
+        for _ in 0..count {
+            let mut best = rng.gen_range(0..fitness.len());
+            for _ in 1..self.size {
+                let candidate = rng.gen_range(0..fitness.len());
+                if compare_fitness(fitness[candidate], fitness[best]) == Ordering::Greater {
+                    best = candidate;
+                }
+            }
+            selected.push(best);
+        }
+        selected
+    }
+
+    fn config(&self) -> Option<SelectionConfig> {
+        Some(SelectionConfig::Tournament { size: self.size })
+    }
+}
+
+impl SelectionStrategy for RouletteSelection {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+        if fitness.is_empty() {
+            return vec![];
+        }
+        roulette(&shift_fitness(fitness), count, rng)
+    }
+
+    fn config(&self) -> Option<SelectionConfig> {
+        Some(SelectionConfig::Roulette)
+    }
+}
+
+impl SelectionStrategy for RankSelection {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+        if fitness.is_empty() {
+            return vec![];
+        }
+        // the worst gets rank 1 and the best gets rank n
+        let mut order: Vec<usize> = (0..fitness.len()).collect();
+        order.sort_by(|a, b| compare_fitness(fitness[*a], fitness[*b]));
+        let mut ranks = vec![0.0; fitness.len()];
+        for (rank, i) in order.iter().enumerate() {
+            ranks[*i] = (rank + 1) as f32;
+        }
+        roulette(&ranks, count, rng)
+    }
+
+    fn config(&self) -> Option<SelectionConfig> {
+        Some(SelectionConfig::Rank)
+    }
+}
+
+impl SelectionStrategy for StochasticUniversalSampling {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+        if fitness.is_empty() || count == 0 {
+            return vec![];
+        }
+        let mut weights = shift_fitness(fitness);
+        let mut total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            weights = vec![1.0; fitness.len()];
+            total = fitness.len() as f32;
+        }
+
+        let distance = total / count as f32;
+        let start = rng.gen::<f32>() * distance;
+        (0..count)
+            .map(|i| spin(&weights, start + i as f32 * distance))
+            .collect()
+    }
+
+    fn config(&self) -> Option<SelectionConfig> {
+        Some(SelectionConfig::StochasticUniversalSampling)
+    }
+}
+
+impl SelectionStrategy for TruncationSelection {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+        if fitness.is_empty() {
+            return vec![];
+        }
+        let mut order: Vec<usize> = (0..fitness.len()).collect();
+        order.sort_by(|a, b| compare_fitness(fitness[*b], fitness[*a]));
+
+        // at least the best one is kept
+        let keep = ((fitness.len() as f32 * self.fraction).ceil() as usize).max(1);
+        let best = &order[..keep.min(fitness.len())];
+        (0..count).map(|_| *best.choose(rng).unwrap()).collect()
+    }
+
+    fn config(&self) -> Option<SelectionConfig> {
+        Some(SelectionConfig::Truncation {
+            fraction: self.fraction,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_selection {
+    use super::*;
+
+    #[test]
+    fn test_tournament_selection() {
+        let selection = TournamentSelection { size: 100 };
+        let selected = selection.select(&[1.0, 3.0, 2.0], 10, &mut rand::thread_rng());
+
+        // with such a big tournament the best is (almost) always in it
+        assert_eq!(selected, vec![1; 10]);
+        assert!(selection
+            .select(&[], 10, &mut rand::thread_rng())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_roulette_selection() {
+        let selected = RouletteSelection.select(&[-2.0, -2.0, 5.0], 10, &mut rand::thread_rng());
+
+        // the worst is shifted to 0 so it can not be selected
+        assert_eq!(selected, vec![2; 10]);
+
+        let selected = RouletteSelection.select(&[1.0, 1.0], 10, &mut rand::thread_rng());
+        assert_eq!(selected.len(), 10);
+        assert!(selected.iter().all(|i| *i < 2));
+    }
+
+    #[test]
+    fn test_rank_selection() {
+        let selected = RankSelection.select(&[-10.0], 3, &mut rand::thread_rng());
+        assert_eq!(selected, vec![0; 3]);
+
+        let selected = RankSelection.select(&[3.0, 100.0, 2.0], 30, &mut rand::thread_rng());
+        assert_eq!(selected.len(), 30);
+        assert!(selected.iter().all(|i| *i < 3));
+    }
+
+    #[test]
+    fn test_stochastic_universal_sampling() {
+        // with the same fitness every candidate is selected exactly once
+        let mut selected =
+            StochasticUniversalSampling.select(&[1.0, 1.0, 1.0, 1.0], 4, &mut rand::thread_rng());
+        selected.sort();
+        assert_eq!(selected, vec![0, 1, 2, 3]);
+
+        let selected =
+            StochasticUniversalSampling.select(&[0.0, 0.0, 5.0], 4, &mut rand::thread_rng());
+        assert_eq!(selected, vec![2; 4]);
+    }
+
+    #[test]
+    fn test_nan_fitness() {
+        // a NaN fitness is the worst, and does not stop the sorting strategies
+        let fitness = [1.0, f32::NAN, 3.0, 2.0];
+        let mut rng = rand::thread_rng();
+        let selected = TruncationSelection { fraction: 0.5 }.select(&fitness, 50, &mut rng);
+        assert!(selected.iter().all(|i| *i == 2 || *i == 3));
+        let selected = RankSelection.select(&fitness, 50, &mut rng);
+        assert_eq!(selected.len(), 50);
+        assert_eq!(
+            compare_fitness(f32::NAN, f32::NEG_INFINITY),
+            Ordering::Equal
+        );
+        assert_eq!(compare_fitness(f32::NAN, -1.0), Ordering::Less);
+    }
+
+    #[test]
+    fn test_nan_fitness_proportionate() {
+        // a NaN fitness is shifted to 0 like the worst, so it is never selected
+        let fitness = [1.0, f32::NAN, 3.0, 2.0, f32::NAN];
+        let mut rng = rand::thread_rng();
+        let selected = RouletteSelection.select(&fitness, 50, &mut rng);
+        assert!(selected.iter().all(|i| *i == 2 || *i == 3));
+        assert!(selected.contains(&2));
+
+        let selected = StochasticUniversalSampling.select(&fitness, 8, &mut rng);
+        assert!(selected.iter().all(|i| *i == 2 || *i == 3));
+        assert!(selected.contains(&2));
+
+        // when every fitness is NaN they are all as good as the other
+        let selected = RouletteSelection.select(&[f32::NAN; 3], 10, &mut rng);
+        assert!(selected.iter().all(|i| *i < 3));
+        let selected = StochasticUniversalSampling.select(&[f32::NAN; 3], 3, &mut rng);
+        assert_eq!(selected.len(), 3);
+    }
+
+    #[test]
+    fn test_nan_fitness_tournament() {
+        // the NaN is drawn first in most tournaments, and has to lose every time
+        let selection = TournamentSelection { size: 100 };
+        let selected = selection.select(&[f32::NAN, 1.0], 10, &mut rand::thread_rng());
+        assert_eq!(selected, vec![1; 10]);
+    }
+
+    #[test]
+    fn test_truncation_selection() {
+        let selection = TruncationSelection { fraction: 0.5 };
+        let selected = selection.select(&[1.0, 4.0, 3.0, 2.0], 20, &mut rand::thread_rng());
+
+        assert!(selected.iter().all(|i| *i == 1 || *i == 2));
+
+        // at least one is kept
+        let selection = TruncationSelection { fraction: 0.0 };
+        let selected = selection.select(&[1.0, 4.0], 5, &mut rand::thread_rng());
+        assert_eq!(selected, vec![1; 5]);
+    }
+
+    #[test]
+    fn test_selection_config() {
+        let configs = [
+            SelectionConfig::Tournament { size: 3 },
+            SelectionConfig::Roulette,
+            SelectionConfig::Rank,
+            SelectionConfig::StochasticUniversalSampling,
+            SelectionConfig::Truncation { fraction: 0.2 },
+        ];
+        for config in configs {
+            assert_eq!(config.strategy().config(), Some(config));
+        }
+    }
+}
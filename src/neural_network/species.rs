@@ -0,0 +1,139 @@
+use super::nn::NN;
+use serde::{Deserialize, Serialize};
+
+// Parameters for dividing the population into species.
+// The compatibility distance is c1 * excess / N + c2 * disjoint / N + c3 * average weight difference
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct SpeciationConfig {
+    pub c1: f32,
+    pub c2: f32,
+    pub c3: f32,
+    pub compatibility_threshold: f32,
+    // the threshold is moved by threshold_step every generation to get this many species
+    pub target_species: usize,
+    pub threshold_step: f32,
+    pub min_threshold: f32,
+}
+
+impl Default for SpeciationConfig {
+    fn default() -> Self {
+        SpeciationConfig {
+            c1: 1.0,
+            c2: 1.0,
+            c3: 0.4,
+            compatibility_threshold: 3.0,
+            target_species: 10,
+            threshold_step: 0.3,
+            min_threshold: 0.3,
+        }
+    }
+}
+
+// When species and the whole population stop improving
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct StagnationConfig {
+    // generations without improvement before a species is removed
+    pub max_stagnation: u32,
+    // the best species are never removed for stagnating
+    pub species_elitism: usize,
+    // generations without a new best in the population before it restarts
+    pub population_stagnation: u32,
+}
+
+impl Default for StagnationConfig {
+    fn default() -> Self {
+        StagnationConfig {
+            max_stagnation: 15,
+            species_elitism: 2,
+            population_stagnation: 1000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Species {
+    pub id: u32,
+    // new genomes are compared to the representative to find their species
+    pub representative: NN,
+    // index of the members in Environment::species
+    pub members: Vec<usize>,
+    // sum of the shared fitness of the members
+    pub adjusted_fitness: f32,
+    // the best fitness of the members in every generation
+    pub fitness_history: Vec<f32>,
+    pub best_fitness: f32,
+    // the generation the best fitness was set
+    pub last_improved: u32,
+}
+
+impl Species {
+    pub fn new(id: u32, representative: NN, first_member: usize, generation: u32) -> Self {
+        Species {
+            id,
+            representative,
+            members: vec![first_member],
+            adjusted_fitness: 0.0,
+            fitness_history: vec![],
+            best_fitness: f32::NEG_INFINITY,
+            last_improved: generation,
+        }
+    }
+
+    pub fn update_fitness(&mut self, fitness: f32, generation: u32) {
+        self.fitness_history.push(fitness);
+        if fitness > self.best_fitness {
+            self.best_fitness = fitness;
+            self.last_improved = generation;
+        }
+    }
+
+    pub fn is_stagnant(&self, generation: u32, max_stagnation: u32) -> bool {
+        generation - self.last_improved > max_stagnation
+    }
+}
+
+#[cfg(test)]
+mod test_species {
+    use super::super::innovation::InnovationTracker;
+    use super::super::mutation::WeightConfig;
+    use super::*;
+
+    #[test]
+    fn test_species_new() {
+        let nn = NN::new(
+            vec![],
+            vec![],
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let specie = Species::new(3, nn, 7, 1);
+
+        assert_eq!(specie.id, 3);
+        assert_eq!(specie.members, vec![7]);
+        assert_eq!(specie.adjusted_fitness, 0.0);
+        assert_eq!(specie.last_improved, 1);
+    }
+
+    #[test]
+    fn test_update_fitness() {
+        let nn = NN::new(
+            vec![],
+            vec![],
+            &mut InnovationTracker::new(0),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        let mut specie = Species::new(0, nn, 0, 1);
+
+        specie.update_fitness(2.0, 2);
+        specie.update_fitness(1.0, 3);
+
+        assert_eq!(specie.fitness_history, vec![2.0, 1.0]);
+        assert_eq!(specie.best_fitness, 2.0);
+        assert_eq!(specie.last_improved, 2);
+
+        assert!(!specie.is_stagnant(4, 2));
+        assert!(specie.is_stagnant(5, 2));
+    }
+}
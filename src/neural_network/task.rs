@@ -0,0 +1,181 @@
+use super::nn::NN;
+use serde::{Deserialize, Serialize};
+
+// A problem the networks are evolved to solve, see Environment::evolve.
+// An episodic task makes an Episode in evaluate and scores it with run_episode.
+// The seed decides the episode, tasks with something random in them should use it
+// instead of thread_rng so a run with a fixed seed can be repeated.
+// The networks are evaluated on many threads at once, so a task is shared between them
+pub trait Task: Sync {
+    fn input_size(&self) -> usize;
+    fn output_size(&self) -> usize;
+
+    // the fitness of the network in one episode, higher is better
+    fn evaluate(&self, nn: &mut NN, seed: u64) -> f32;
+}
+
+// One run of an episodic task, the network makes a move until it is done
+pub trait Episode {
+    // the inputs of the network for the next move
+    fn observation(&self) -> Vec<f32>;
+    // makes the move the outputs of the network decide, and returns the reward for it
+    fn step(&mut self, outputs: &[f32]) -> f32;
+    fn done(&self) -> bool;
+}
+
+// plays the episode to the end, the fitness is the sum of the rewards
+pub fn run_episode(nn: &mut NN, episode: &mut dyn Episode) -> f32 {
+    // the network should not remember the last episode
+    nn.reset_state();
+    let mut total = 0.0;
+    while !episode.done() {
+        let outputs = nn.activate(&episode.observation());
+        total += episode.step(&outputs);
+    }
+    total
+}
+
+// How the fitness of the episodes a network plays are made into one fitness
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Aggregation {
+    Mean,
+    Median,
+    // the worst episode, for networks that have to be good on every episode
+    Min,
+    // the mean without the given fraction of the worst and the best episodes
+    TrimmedMean(f32),
+}
+
+impl Aggregation {
+    pub fn aggregate(&self, mut scores: Vec<f32>) -> f32 {
+        scores.sort_by(|a, b| a.total_cmp(b));
+        let len = scores.len();
+        match self {
+            Aggregation::Mean => mean(&scores),
+            // the middle score, or the mean of the two in the middle
+            Aggregation::Median => (scores[(len - 1) / 2] + scores[len / 2]) / 2.0,
+            Aggregation::Min => scores[0],
+            Aggregation::TrimmedMean(fraction) => {
+                // at least one episode is left
+                let cut = ((len as f32 * fraction) as usize).min((len - 1) / 2);
+                mean(&scores[cut..len - cut])
+            }
+        }
+    }
+}
+
+fn mean(scores: &[f32]) -> f32 {
+    scores.iter().sum::<f32>() / scores.len() as f32
+}
+
+// How many episodes every network plays each generation.
+// Every network gets the same seeds, so they are compared on the same episodes,
+// and the seeds are new every generation
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct EvaluationConfig {
+    pub episodes: usize,
+    pub aggregation: Aggregation,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        EvaluationConfig {
+            episodes: 1,
+            aggregation: Aggregation::Mean,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_task {
+    use super::super::activation_functions::ActivationFunctions;
+    use super::super::genes::{NodeGene, NodeType};
+    use super::super::innovation::InnovationTracker;
+    use super::super::mutation::WeightConfig;
+    use super::*;
+
+    // the reward is the output, for a fixed number of moves
+    struct Echo {
+        moves: u32,
+    }
+
+    impl Episode for Echo {
+        fn observation(&self) -> Vec<f32> {
+            vec![1.0]
+        }
+
+        fn step(&mut self, outputs: &[f32]) -> f32 {
+            self.moves -= 1;
+            outputs[0]
+        }
+
+        fn done(&self) -> bool {
+            self.moves == 0
+        }
+    }
+
+    struct EchoTask;
+
+    impl Task for EchoTask {
+        fn input_size(&self) -> usize {
+            1
+        }
+
+        fn output_size(&self) -> usize {
+            1
+        }
+
+        fn evaluate(&self, nn: &mut NN, seed: u64) -> f32 {
+            let mut episode = Echo {
+                moves: seed as u32 + 1,
+            };
+            run_episode(nn, &mut episode)
+        }
+    }
+
+    #[test]
+    fn test_episodic_evaluate() {
+        let input = vec![NodeGene::new(
+            0,
+            NodeType::Input,
+            0.0,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let output = vec![NodeGene::new(
+            1,
+            NodeType::Output,
+            0.5,
+            ActivationFunctions::None,
+            0.0,
+        )];
+        let mut nn = NN::new(
+            input,
+            output,
+            &mut InnovationTracker::new(2),
+            &WeightConfig::default(),
+            &mut rand::thread_rng(),
+        );
+        nn.connection_genes[0].weight = 2.0;
+
+        assert_eq!(EchoTask.evaluate(&mut nn, 2), 3.0 * 2.5);
+        assert_eq!(EchoTask.evaluate(&mut nn, 0), 2.5);
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let scores = vec![4.0, -10.0, 1.0, 3.0, 2.0, 20.0];
+        assert_eq!(Aggregation::Mean.aggregate(scores.clone()), 20.0 / 6.0);
+        assert_eq!(Aggregation::Median.aggregate(scores.clone()), 2.5);
+        assert_eq!(Aggregation::Median.aggregate(vec![3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(Aggregation::Min.aggregate(scores.clone()), -10.0);
+        assert_eq!(Aggregation::TrimmedMean(0.2).aggregate(scores.clone()), 2.5);
+        assert_eq!(
+            Aggregation::TrimmedMean(0.0).aggregate(scores.clone()),
+            20.0 / 6.0
+        );
+        // trimming everything leaves the median
+        assert_eq!(Aggregation::TrimmedMean(0.5).aggregate(scores), 2.5);
+        assert_eq!(Aggregation::TrimmedMean(0.5).aggregate(vec![5.0]), 5.0);
+    }
+}
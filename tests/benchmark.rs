@@ -0,0 +1,15 @@
+use evolutionary_nn::neural_network::benchmark::{run_benchmark, ParityTask};
+use evolutionary_nn::neural_network::environment::Environment;
+
+#[test]
+fn test_xor_is_solved() {
+    // a fixed seed, so the run is the same every time
+    let task = ParityTask::xor();
+    let mut env = Environment::with_seed(150, 2, 1, 1);
+    let result = run_benchmark(&mut env, &task, 100).unwrap();
+    assert!(result.generations.is_some());
+
+    // a network without a hidden node can not solve it
+    assert!(result.nodes > 3);
+    assert!(env.species.iter_mut().any(|nn| task.solves(nn)));
+}